@@ -0,0 +1,307 @@
+#![allow(warnings)]
+// Replays the game_info history of one mode through the settlement of the server
+// (rate_game and score_game with the mode's GameModeCfg) and a parameter set,
+// to see what a K or rating algorithm change would do before it goes live.
+//
+//   cargo run --bin recompute -- rk5p2t --k 24 --output ratings.jsonl
+//   cargo run --bin recompute -- rk5p2t --input game_info.jsonl --rating glicko2
+//
+// The JSONL input holds one game_info row per line: {"gameid":1,"id":"steamid","Res":true}
+// with an optional "mode", "afk" and the damage, be_damage, K, D, A and battle_score stats.
+// Rows saved before game_info kept battle_score replay the performance modifier with 0.
+// Ratings are compared with the live mode table when MySQL is reachable.
+//
+// game_info keeps only a win or a loss, so modes with more than two teams are refused.
+// Not replayed, so they show in the diff vs live: trueskill partial play weights (every
+// player weighs 1), ratings held by an open review, decay and season resets.
+#[path = "../elo.rs"]
+mod elo;
+#[path = "../event_room.rs"]
+mod event_room;
+#[path = "../room.rs"]
+mod room;
+#[path = "../msg.rs"]
+mod msg;
+#[path = "../leaderboard.rs"]
+mod leaderboard;
+#[path = "../review.rs"]
+mod review;
+#[path = "../queue.rs"]
+mod queue;
+
+use std::collections::BTreeMap;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufReader;
+use clap::{App, Arg};
+use failure::Error;
+use mysql;
+use serde_derive::{Serialize, Deserialize};
+use serde_json::json;
+use crate::elo::*;
+use crate::event_room::{Config, GameModeCfg, RankTierCfg, GameRating, get_tier_cfg, rate_game, score_game};
+use crate::room::ScoreInfo;
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct ServerSetting {
+    SQL_IP: Option<String>,
+    MYSQL_ACCOUNT: Option<String>,
+    MYSQL_PASSWORD: Option<String>,
+}
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct Setting {
+    server_setting: Option<ServerSetting>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct GameRow {
+    gameid: u32,
+    id: String,
+    Res: bool,
+    #[serde(default)]
+    mode: String,
+    // stats of the performance modifier and the leaver flag, as game_info keeps them
+    #[serde(default)]
+    afk: bool,
+    #[serde(default)]
+    damage: u16,
+    #[serde(default)]
+    be_damage: u16,
+    #[serde(default)]
+    K: u16,
+    #[serde(default)]
+    D: u16,
+    #[serde(default)]
+    A: u16,
+    #[serde(default)]
+    battle_score: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+struct PlayerRes {
+    id: String,
+    games: u32,
+    score: i32,
+    mmr: i32,
+    live: Option<i32>,
+    diff: Option<i32>,
+}
+
+fn read_toml<T: serde::de::DeserializeOwned>(path: &str) -> Result<T, Error> {
+    let mut str_val = String::new();
+    File::open(path)?.read_to_string(&mut str_val)?;
+    Ok(toml::from_str(&str_val)?)
+}
+
+fn get_url(setting: &Setting) -> String {
+    let s = setting.server_setting.clone().unwrap();
+    format!("mysql://{}:{}@{}:4000/erps", s.MYSQL_ACCOUNT.unwrap(), s.MYSQL_PASSWORD.unwrap(), s.SQL_IP.unwrap())
+}
+
+// game_info rows of the mode in game order, older rows without a mode take it from the ledger
+fn load_sql(conn: &mut mysql::PooledConn, mode: &str) -> Result<Vec<GameRow>, Error> {
+    let sql = format!(r#"select g.gameid as gameid, u.userid as userid, g.Res as Res, g.afk as afk, g.damage as damage, g.be_damage as be_damage,
+        g.K as K, g.D as D, g.A as A, g.battle_score as battle_score from game_info as g join user as u on u.id=g.id
+        left join (select distinct gameid, mode from rating_ledger where gameid>0) as l on l.gameid=g.gameid
+        where coalesce(nullif(g.mode, ''), l.mode)='{}' order by g.gameid;"#, mode);
+    let mut res = vec![];
+    for row in conn.query(sql)? {
+        let a = row?.clone();
+        res.push(GameRow {
+            gameid: mysql::from_value(a.get("gameid").unwrap()),
+            id: mysql::from_value(a.get("userid").unwrap()),
+            Res: mysql::from_value(a.get("Res").unwrap()),
+            mode: mode.to_owned(),
+            afk: mysql::from_value(a.get("afk").unwrap()),
+            damage: mysql::from_value(a.get("damage").unwrap()),
+            be_damage: mysql::from_value(a.get("be_damage").unwrap()),
+            K: mysql::from_value(a.get("K").unwrap()),
+            D: mysql::from_value(a.get("D").unwrap()),
+            A: mysql::from_value(a.get("A").unwrap()),
+            battle_score: mysql::from_value(a.get("battle_score").unwrap()),
+        });
+    }
+    Ok(res)
+}
+
+fn load_jsonl(path: &str, mode: &str) -> Result<Vec<GameRow>, Error> {
+    let mut res = vec![];
+    for line in BufReader::new(File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().len() == 0 {
+            continue;
+        }
+        let row: GameRow = serde_json::from_str(&line)?;
+        if row.mode.len() == 0 || row.mode == mode {
+            res.push(row);
+        }
+    }
+    Ok(res)
+}
+
+fn load_live(conn: &mut mysql::PooledConn, mode: &str) -> Result<BTreeMap<String, i32>, Error> {
+    let sql = format!("select userid, a.mmr as Mmr from user as f join {} as a on a.id=f.id;", mode);
+    let mut res = BTreeMap::new();
+    for row in conn.query(sql)? {
+        let a = row?.clone();
+        res.insert(mysql::from_value(a.get("userid").unwrap()), mysql::from_value(a.get("Mmr").unwrap()));
+    }
+    Ok(res)
+}
+
+// rates one game the way settlement does and returns the winners' win probability
+fn replay(gm: &GameModeCfg, tiers: &Vec<RankTierCfg>, players: &mut BTreeMap<String, ScoreInfo>, teams: &Vec<Vec<GameRow>>) -> f32 {
+    let ids: Vec<Vec<String>> = teams.iter().map(|t| t.iter().map(|x| x.id.clone()).collect()).collect();
+    let infos: Vec<Vec<ScoreInfo>> = ids.iter()
+        .map(|t| t.iter().map(|id| players.entry(id.clone()).or_insert(gm.new_score()).clone()).collect())
+        .collect();
+    let mut modifier: BTreeMap<String, f32> = BTreeMap::new();
+    if let Some(perf) = &gm.perf {
+        for (i, team) in teams.iter().enumerate() {
+            let stats: Vec<PerformanceStats> = team.iter().map(|x| PerformanceStats {
+                damage: x.damage as f32,
+                be_damage: x.be_damage as f32,
+                K: x.K as f32,
+                D: x.D as f32,
+                A: x.A as f32,
+                battle_score: x.battle_score,
+            }).collect();
+            for (x, m) in team.iter().zip(perf.modifier(&stats, i == 0)) {
+                modifier.insert(x.id.clone(), m);
+            }
+        }
+    }
+    let afk: Vec<String> = teams.iter().flatten().filter(|x| x.afk).map(|x| x.id.clone()).collect();
+    let rated: Vec<Vec<GameRating>> = rate_game(gm, &ids, &infos, &BTreeMap::new(), &modifier, &afk);
+    for (i, team) in ids.iter().enumerate() {
+        for (j, id) in team.iter().enumerate() {
+            let r = &rated[i][j];
+            let info = players.get_mut(id).unwrap();
+            info.rd = r.rd;
+            info.vol = r.vol;
+            info.sigma = r.sigma;
+            score_game(info, r.value, gm, tiers, r.win);
+        }
+    }
+    rated[0].iter().map(|r| r.expected).sum::<f32>()/rated[0].len() as f32
+}
+
+fn main() -> std::result::Result<(), Error> {
+    let matches = App::new("recompute")
+        .about("Replays the game history of a mode with a rating parameter set")
+        .arg(Arg::with_name("MODE").required(true).help("game mode, e.g. rk5p2t"))
+        .arg(Arg::with_name("CONFIG").long("config").takes_value(true).help("config with the mode parameters (src/config.toml)"))
+        .arg(Arg::with_name("SETTING").long("setting").takes_value(true).help("MySQL setting (src/setting.toml)"))
+        .arg(Arg::with_name("INPUT").long("input").takes_value(true).help("exported game_info JSONL instead of MySQL"))
+        .arg(Arg::with_name("OUTPUT").long("output").takes_value(true).help("write the ratings as JSONL instead of stdout"))
+        .arg(Arg::with_name("RATING").long("rating").takes_value(true).help("elo, glicko2 or trueskill"))
+        .arg(Arg::with_name("K").long("k").takes_value(true).help("elo K for every player"))
+        .arg(Arg::with_name("TAU").long("tau").takes_value(true).help("glicko2 tau"))
+        .arg(Arg::with_name("BETA").long("beta").takes_value(true).help("trueskill beta"))
+        .arg(Arg::with_name("WRITE").long("write").help("write the recomputed ratings back to the mode table"))
+        .get_matches();
+
+    let mode = matches.value_of("MODE").unwrap();
+    let config: Config = read_toml(matches.value_of("CONFIG").unwrap_or("src/config.toml"))?;
+    let mut gm = config.game_mode.clone().unwrap_or_default().iter()
+        .find(|x| x.MODE.as_ref().map(|m| m.as_str()) == Some(mode))
+        .map(|x| GameModeCfg::new(x))
+        .ok_or(failure::err_msg(format!("mode {} not in config", mode)))?;
+    if gm.match_size > 2 {
+        return Err(failure::err_msg(format!("mode {} has {} teams, game_info only keeps win or loss", mode, gm.match_size)));
+    }
+    let tiers = get_tier_cfg(config.rank_tier.clone().unwrap_or_default());
+    if let Some(r) = matches.value_of("RATING") {
+        gm.rating = r.to_owned();
+    }
+    if let Some(k) = matches.value_of("K") {
+        let k: f32 = k.parse()?;
+        gm.k = KSchedule {k: k, k_new: k, new_games: 0, k_high: k, high_score: std::i32::MAX, k_provisional: k, provisional_games: 0};
+    }
+    if let Some(tau) = matches.value_of("TAU") {
+        gm.tau = tau.parse()?;
+    }
+    if let Some(beta) = matches.value_of("BETA") {
+        gm.beta = beta.parse()?;
+    }
+    println!("{} {:?}", mode, gm);
+
+    let setting: Setting = read_toml(matches.value_of("SETTING").unwrap_or("src/setting.toml"))?;
+    let input = matches.value_of("INPUT");
+    let pool = match mysql::Pool::new(get_url(&setting).as_str()) {
+        Ok(pool) => Some(pool),
+        // a file replay still works offline, only the live diff is missing
+        Err(e) if input.is_some() && !matches.is_present("WRITE") => {
+            println!("no live ratings: {}", e);
+            None
+        }
+        Err(e) => return Err(e.into()),
+    };
+    let rows = match (input, &pool) {
+        (Some(path), _) => load_jsonl(path, mode)?,
+        (None, Some(pool)) => load_sql(&mut pool.get_conn()?, mode)?,
+        (None, None) => vec![],
+    };
+    let live = match &pool {
+        Some(pool) => load_live(&mut pool.get_conn()?, mode)?,
+        None => BTreeMap::new(),
+    };
+
+    // winners first, the order settlement rates the teams in
+    let mut games: BTreeMap<u32, Vec<Vec<GameRow>>> = BTreeMap::new();
+    for row in rows {
+        let g = games.entry(row.gameid).or_insert(vec![vec![], vec![]]);
+        g[if row.Res { 0 } else { 1 }].push(row);
+    }
+    let mut players: BTreeMap<String, ScoreInfo> = BTreeMap::new();
+    let mut calibration = Calibration::default();
+    let mut skipped = 0;
+    for (_, teams) in &games {
+        if teams.iter().any(|t| t.len() == 0) {
+            skipped += 1;
+            continue;
+        }
+        let e = replay(&gm, &tiers, &mut players, teams);
+        calibration.add(e, true);
+    }
+
+    let mut out: Box<dyn Write> = match matches.value_of("OUTPUT") {
+        Some(path) => Box::new(File::create(path)?),
+        None => Box::new(std::io::stdout()),
+    };
+    let mut diffs: Vec<i32> = vec![];
+    for (id, x) in &players {
+        let l = live.get(id).cloned();
+        let diff = l.map(|l| x.mmr - l);
+        if let Some(d) = diff {
+            diffs.push(d);
+        }
+        let res = PlayerRes {id: id.clone(), games: x.WinCount + x.LoseCount, score: x.score, mmr: x.mmr, live: l, diff: diff};
+        writeln!(out, "{}", json!(res))?;
+    }
+    println!("games: {}, skipped: {}, players: {}", games.len()-skipped, skipped, players.len());
+    println!("brier: {:.4}, log loss: {:.4}, accuracy: {:.4}", calibration.brier(), calibration.log_loss(), calibration.accuracy());
+    for (i, b) in calibration.buckets.iter().enumerate() {
+        if b.count > 0 {
+            println!("  {:.1}-{:.1}: {} predicted {:.3} won {:.3}", i as f32/CALIBRATION_BUCKETS as f32, (i+1) as f32/CALIBRATION_BUCKETS as f32,
+                b.count, b.predicted/b.count as f64, b.wins as f64/b.count as f64);
+        }
+    }
+    if diffs.len() > 0 {
+        let abs: Vec<i32> = diffs.iter().map(|d| d.abs()).collect();
+        println!("diff vs live: mean {:.1}, mean abs {:.1}, max abs {}", mean(&diffs), mean(&abs), abs.iter().max().unwrap());
+    }
+
+    if matches.is_present("WRITE") {
+        let pool = pool.unwrap();
+        let mut conn = pool.get_conn()?;
+        let mut t = conn.start_transaction(false, None, None)?;
+        for (id, x) in &players {
+            t.query(format!("update {} set score={}, mmr={}, rd={}, vol={}, sigma={} where id=(select id from user where userid='{}');",
+                mode, x.score, x.mmr, x.rd, x.vol, x.sigma, id))?;
+        }
+        t.commit()?;
+        println!("wrote {} ratings to {}", players.len(), mode);
+    }
+    Ok(())
+}
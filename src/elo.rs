@@ -1,8 +1,137 @@
 
+use std::f64::consts::PI;
+use serde_derive::{Serialize, Deserialize};
+use rust_decimal::{Decimal, RoundingStrategy};
+use rust_decimal::prelude::*;
+
+pub const GLICKO2_SCALE: f64 = 173.7178;
+pub const GLICKO2_DEFAULT_RD: f32 = 350.0;
+pub const GLICKO2_DEFAULT_VOL: f32 = 0.06;
+pub const TRUESKILL_KAPPA: f32 = 0.0001;
+pub const TRUESKILL_DEFAULT_SIGMA: f32 = 350.0;
+
 pub struct EloRank {
     pub k: f32,
 }
 
+// K-factor by experience: provisional players first, then new players,
+// then high rated players, everyone else uses k
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct KSchedule {
+    pub k: f32,
+    pub k_new: f32,
+    pub new_games: u32,
+    pub k_high: f32,
+    pub high_score: i32,
+    pub k_provisional: f32,
+    pub provisional_games: u32,
+}
+
+pub struct Glicko2 {
+    pub tau: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Glicko2Rating {
+    pub rating: f32,
+    pub rd: f32,
+    pub vol: f32,
+}
+
+// Weng-Lin bayesian approximation (Bradley-Terry, full pairing),
+// a TrueSkill-like model that rates every team against every other team
+pub struct TrueSkill {
+    pub beta: f32,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct SkillRating {
+    pub mu: f32,
+    pub sigma: f32,
+}
+
+// accuracy of win probability predictions, every game is counted from both
+// sides so the buckets compare the predicted and observed win rate
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct Calibration {
+    pub games: u32,
+    pub correct: u32,
+    pub brier_sum: f64,
+    pub log_loss_sum: f64,
+    pub buckets: Vec<CalibrationBucket>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct CalibrationBucket {
+    pub count: u32,
+    pub predicted: f64,
+    pub wins: u32,
+}
+
+pub const CALIBRATION_BUCKETS: usize = 10;
+
+// Fixed point rating math. Elo and every adjustment of a persisted score run on
+// Decimal so a primary, a backup and the recompute tool land on the same points.
+// Rounding:
+// - f32 inputs (K, factors) are quantized to INPUT_DP places, half to even,
+//   f32 holds about 7 significant digits so this drops its binary noise
+// - team means are kept to RATING_DP places, half to even
+// - 10^x is a Taylor series on Decimal, the expectation is kept to RATING_DP places, half to even
+// - a change in points is rounded to a whole point half away from zero, like f32::round
+// Glicko-2 and TrueSkill keep their floating point solvers, only the result is rounded here.
+pub const RATING_DP: u32 = 8;
+pub const INPUT_DP: u32 = 6;
+// intermediate precision of the series
+const SERIES_DP: u32 = 20;
+
+pub fn to_fixed(x: f32) -> Decimal {
+    Decimal::from_f32(x).unwrap_or_default().round_dp(INPUT_DP)
+}
+
+pub fn round_points(x: Decimal) -> i32 {
+    let r = x.abs().round_dp_with_strategy(0, RoundingStrategy::RoundHalfUp).to_i32().unwrap_or(std::i32::MAX);
+    if x.is_sign_negative() { -r } else { r }
+}
+
+// points scaled by a factor, e.g. a modifier or a soft reset ratio
+pub fn apply_ratio(points: i32, ratio: f32) -> i32 {
+    round_points(Decimal::from(points) * to_fixed(ratio))
+}
+
+pub fn mean_fixed(numbers: &Vec<i32>) -> Decimal {
+    let sum: i64 = numbers.iter().map(|x| *x as i64).sum();
+    (Decimal::from(sum) / Decimal::from(numbers.len().max(1))).round_dp(RATING_DP)
+}
+
+fn exp_fixed(x: Decimal) -> Decimal {
+    // halve until |x| <= 1/2, sum the series, then square back
+    let half = Decimal::new(5, 1);
+    let mut x = x;
+    let mut n = 0;
+    while x.abs() > half {
+        x = x / Decimal::from(2);
+        n += 1;
+    }
+    let mut sum = Decimal::one();
+    let mut term = Decimal::one();
+    for i in 1..40 {
+        term = (term * x / Decimal::from(i)).round_dp(SERIES_DP);
+        if term.is_zero() {
+            break;
+        }
+        sum += term;
+    }
+    for _ in 0..n {
+        sum = (sum * sum).round_dp(SERIES_DP);
+    }
+    sum
+}
+
+fn pow10_fixed(x: Decimal) -> Decimal {
+    let ln10 = Decimal::from_i128_with_scale(230258509299404568401799145, 26);
+    exp_fixed(x * ln10)
+}
+
 pub fn mean(numbers: &Vec<i32>) -> f32 {
     let sum: i32 = numbers.iter().sum();
     sum as f32 / numbers.len() as f32
@@ -20,10 +149,18 @@ pub fn median(numbers: &mut Vec<i32>) -> i32 {
 
 impl EloRank {
     pub fn get_expected(&self, a: f32, b: f32) -> f32 {
-        return 1.0/(1.0+10f32.powf((b-a)/400f32));
+        self.get_expected_fixed(to_fixed(a), to_fixed(b)).to_f64().unwrap_or(0.5) as f32
+    }
+    pub fn get_expected_fixed(&self, a: Decimal, b: Decimal) -> Decimal {
+        // beyond 4000 points apart the expectation is 0 or 1 to RATING_DP anyway
+        let d = ((b-a)/Decimal::from(400)).max(Decimal::from(-10)).min(Decimal::from(10));
+        (Decimal::one()/(Decimal::one()+pow10_fixed(d))).round_dp(RATING_DP)
     }
     pub fn rating(&self, expected: f32, actual: f32, current: f32) -> f32 {
-        return (current+ self.k*(actual-expected)).round();
+        (round_points(to_fixed(current)) + self.delta_fixed(to_fixed(expected), to_fixed(actual))) as f32
+    }
+    pub fn delta_fixed(&self, expected: Decimal, actual: Decimal) -> i32 {
+        round_points(to_fixed(self.k) * (actual-expected))
     }
     pub fn compute_elo(&self, win: i32, lose: i32)
         -> (i32, i32) {
@@ -36,34 +173,394 @@ impl EloRank {
     
     pub fn compute_elo_team(&self, winteam: &Vec<i32>, loseteam: &Vec<i32>)
         -> (Vec<i32>, Vec<i32>) {
-        let win = mean(winteam);
-        let lose = mean(loseteam);
+        self.compute_elo_team_k(winteam, loseteam, &vec![self.k; winteam.len()], &vec![self.k; loseteam.len()])
+    }
+    // same as compute_elo_team with a K-factor for every player
+    pub fn compute_elo_team_k(&self, winteam: &Vec<i32>, loseteam: &Vec<i32>, wink: &Vec<f32>, losek: &Vec<f32>)
+        -> (Vec<i32>, Vec<i32>) {
+        let win = mean_fixed(winteam);
+        let lose = mean_fixed(loseteam);
         let mut wint = vec![];
         let mut loset = vec![];
-        for score in winteam {
-            let ewin = self.get_expected(*score as f32, lose as f32);
-            let rwin = self.rating(ewin as f32, 1.0, *score as f32);
-            wint.push(rwin as i32);
+        for (i, score) in winteam.iter().enumerate() {
+            let elo = EloRank {k: wink[i]};
+            let ewin = elo.get_expected_fixed(Decimal::from(*score), lose);
+            wint.push(score + elo.delta_fixed(ewin, Decimal::one()));
         }
-        for score in loseteam {
-            let elose = self.get_expected(*score as f32, win as f32);
-            let rlose = self.rating(elose as f32, 0.0, *score as f32);
-            loset.push(rlose as i32);
+        for (i, score) in loseteam.iter().enumerate() {
+            let elo = EloRank {k: losek[i]};
+            let elose = elo.get_expected_fixed(Decimal::from(*score), win);
+            loset.push(score + elo.delta_fixed(elose, Decimal::zero()));
         }
         (wint, loset)
     }
+    // teams in placement order, every team plays each other team once and
+    // scores 1.0 against the teams behind it. Two teams match compute_elo_team_k.
+    pub fn compute_elo_placement_k(&self, teams: &Vec<Vec<i32>>, k: &Vec<Vec<f32>>)
+        -> Vec<Vec<i32>> {
+        let n = teams.len();
+        if n < 2 {
+            return teams.clone();
+        }
+        let means: Vec<Decimal> = teams.iter().map(|t| mean_fixed(t)).collect();
+        let mut res = vec![];
+        for (i, team) in teams.iter().enumerate() {
+            let actual = Decimal::from(n-1-i)/Decimal::from(n-1);
+            let mut rt = vec![];
+            for (j, score) in team.iter().enumerate() {
+                let elo = EloRank {k: k[i][j]};
+                let e = self.expected_placement_fixed(Decimal::from(*score), &means, i);
+                rt.push(score + elo.delta_fixed(e, actual));
+            }
+            res.push(rt);
+        }
+        res
+    }
+    // mean expected score of a player of team i against every other team
+    pub fn get_expected_placement(&self, score: f32, means: &Vec<f32>, i: usize) -> f32 {
+        let means: Vec<Decimal> = means.iter().map(|m| to_fixed(*m)).collect();
+        self.expected_placement_fixed(to_fixed(score), &means, i).to_f64().unwrap_or(0.5) as f32
+    }
+    fn expected_placement_fixed(&self, score: Decimal, means: &Vec<Decimal>, i: usize) -> Decimal {
+        if means.len() < 2 {
+            return Decimal::new(5, 1);
+        }
+        let e: Decimal = means.iter().enumerate()
+            .filter(|(t, _)| *t != i)
+            .map(|(_, m)| self.get_expected_fixed(score, *m))
+            .sum();
+        (e/Decimal::from(means.len()-1)).round_dp(RATING_DP)
+    }
+    // entries in placement order, the first win_mount entries score above 0.5.
+    // win_mount = len/2 with scale 0.5 keeps the actual scores centered on 0.5
     pub fn compute_elo_battle_ground(&self, team: &Vec<i32>, win_mount: usize, scale: f32)
         -> Vec<i32> {
-        let m = mean(team);
-        let mut rest = vec![];
-        let mut a = win_mount as f32 *scale + 0.25;
-        for (i, score) in team.iter().enumerate() {
-            let ewin = self.get_expected(*score as f32, m as f32);
-            let rwin = self.rating(ewin as f32, a, *score as f32);
+        let teams = team.iter().map(|x| vec![*x]).collect();
+        let (res, _) = self.compute_elo_battle_ground_k(&teams, win_mount, scale, &vec![vec![self.k]; team.len()]);
+        res.into_iter().flatten().collect()
+    }
+    // teams in placement order are the entries, rated on their rounded means.
+    // every player moves at their own K, the team expectations come back for the ledger
+    pub fn compute_elo_battle_ground_k(&self, teams: &Vec<Vec<i32>>, win_mount: usize, scale: f32, k: &Vec<Vec<f32>>)
+        -> (Vec<Vec<i32>>, Vec<Decimal>) {
+        let means: Vec<i32> = teams.iter().map(|t| round_points(mean_fixed(t))).collect();
+        let total = mean_fixed(&means);
+        let scale = to_fixed(scale);
+        let mut a = Decimal::from(win_mount) * scale + Decimal::new(25, 2);
+        let (mut res, mut es) = (vec![], vec![]);
+        for (i, team) in teams.iter().enumerate() {
+            let e = self.get_expected_fixed(Decimal::from(means[i]), total);
+            res.push(team.iter().enumerate().map(|(j, score)| score + EloRank {k: k[i][j]}.delta_fixed(e, a)).collect());
+            es.push(e);
             a -= scale;
-            rest.push(rwin as i32);
         }
-        rest
+        (res, es)
+    }
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct PerformanceStats {
+    pub damage: f32,
+    pub be_damage: f32,
+    pub K: f32,
+    pub D: f32,
+    pub A: f32,
+    pub battle_score: f32,
+}
+
+// scales rating deltas by a player's share of the team contribution
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct PerformanceCfg {
+    pub damage: f32,
+    pub be_damage: f32,
+    pub kill: f32,
+    pub death: f32,
+    pub assist: f32,
+    pub battle_score: f32,
+    pub strength: f32,
+    pub min: f32,
+    pub max: f32,
+}
+
+impl PerformanceCfg {
+    pub fn contribution(&self, s: &PerformanceStats) -> f32 {
+        (self.damage*s.damage + self.be_damage*s.be_damage + self.kill*s.K - self.death*s.D
+            + self.assist*s.A + self.battle_score*s.battle_score).max(0.0)
+    }
+    // winners above the team average gain more, losers above it lose less
+    pub fn modifier(&self, team: &Vec<PerformanceStats>, win: bool) -> Vec<f32> {
+        let c: Vec<f32> = team.iter().map(|s| self.contribution(s)).collect();
+        let m = c.iter().sum::<f32>() / c.len().max(1) as f32;
+        c.iter().map(|x| {
+            let r = if m > 0.0 { x/m } else { 1.0 };
+            let d = self.strength*(r-1.0);
+            let v = if win { 1.0+d } else { 1.0-d };
+            v.max(self.min).min(self.max)
+        }).collect()
+    }
+}
+
+// how the visible score follows the hidden mmr
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct MmrCfg {
+    pub gain: f32,
+    pub loss: f32,
+    pub converge: f32,
+}
+
+impl MmrCfg {
+    // a win never lowers the visible score and a loss never raises it
+    pub fn visible_delta(&self, score: i32, mmr: i32, delta: i32, win: bool) -> i32 {
+        let factor = to_fixed(if win { self.gain } else { self.loss });
+        let d = round_points(Decimal::from(delta) * factor + Decimal::from(mmr + delta - score) * to_fixed(self.converge));
+        if win { d.max(0) } else { d.min(0) }
+    }
+}
+
+// bonus on visible gains from the min-th win in a row, bonus per win up to max
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct StreakCfg {
+    pub min: i32,
+    pub bonus: f32,
+    pub max: f32,
+}
+
+impl StreakCfg {
+    pub fn multiplier(&self, streak: i32) -> f32 {
+        if streak < self.min {
+            return 1.0;
+        }
+        1.0 + (self.bonus * (streak - self.min + 1) as f32).min(self.max)
+    }
+}
+
+// offset of a player's mmr on one hero, moved by k per game against the prediction
+// and kept within max, a hero never played starts at -new
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct HeroCfg {
+    pub k: f32,
+    pub new: i32,
+    pub max: i32,
+}
+
+impl HeroCfg {
+    pub fn update(&self, mmr: i32, expected: f32, win: bool) -> i32 {
+        let actual = if win { Decimal::from(1) } else { Decimal::from(0) };
+        (mmr + round_points(to_fixed(self.k) * (actual - to_fixed(expected)))).max(-self.max).min(self.max)
+    }
+}
+
+impl KSchedule {
+    pub fn get_k(&self, games: u32, score: i32) -> f32 {
+        if games < self.provisional_games {
+            self.k_provisional
+        } else if games < self.new_games {
+            self.k_new
+        } else if score >= self.high_score {
+            self.k_high
+        } else {
+            self.k
+        }
+    }
+}
+
+impl Glicko2 {
+    fn g(phi: f64) -> f64 {
+        1.0/(1.0+3.0*phi*phi/(PI*PI)).sqrt()
+    }
+    fn e(mu: f64, mu_j: f64, phi_j: f64) -> f64 {
+        1.0/(1.0+(-Glicko2::g(phi_j)*(mu-mu_j)).exp())
+    }
+    pub fn get_expected(&self, a: &Glicko2Rating, b: &Glicko2Rating) -> f32 {
+        Glicko2::e(a.rating as f64/GLICKO2_SCALE, b.rating as f64/GLICKO2_SCALE, b.rd as f64/GLICKO2_SCALE) as f32
+    }
+    // one rating period for `player` against (opponent, actual score) pairs
+    pub fn rating(&self, player: &Glicko2Rating, games: &Vec<(Glicko2Rating, f32)>) -> Glicko2Rating {
+        let mu = player.rating as f64/GLICKO2_SCALE;
+        let phi = player.rd as f64/GLICKO2_SCALE;
+        let sigma = player.vol as f64;
+        if games.len() == 0 {
+            let phi_star = (phi*phi + sigma*sigma).sqrt();
+            return Glicko2Rating {rating: player.rating, rd: (phi_star*GLICKO2_SCALE).min(GLICKO2_DEFAULT_RD as f64) as f32, vol: player.vol};
+        }
+        let mut v_inv = 0.0;
+        let mut sum = 0.0;
+        for (opp, s) in games {
+            let mu_j = opp.rating as f64/GLICKO2_SCALE;
+            let phi_j = opp.rd as f64/GLICKO2_SCALE;
+            let g = Glicko2::g(phi_j);
+            let e = Glicko2::e(mu, mu_j, phi_j);
+            v_inv += g*g*e*(1.0-e);
+            sum += g*(*s as f64-e);
+        }
+        let v = 1.0/v_inv;
+        let delta = v*sum;
+        // new volatility, Illinois algorithm as in Glickman's paper
+        let tau = self.tau as f64;
+        let a = (sigma*sigma).ln();
+        let f = |x: f64| -> f64 {
+            let ex = x.exp();
+            ex*(delta*delta-phi*phi-v-ex)/(2.0*(phi*phi+v+ex).powi(2)) - (x-a)/(tau*tau)
+        };
+        let mut A = a;
+        let mut B;
+        if delta*delta > phi*phi+v {
+            B = (delta*delta-phi*phi-v).ln();
+        } else {
+            let mut k = 1.0;
+            while f(a-k*tau) < 0.0 {
+                k += 1.0;
+            }
+            B = a-k*tau;
+        }
+        let mut fA = f(A);
+        let mut fB = f(B);
+        while (B-A).abs() > 0.000001 {
+            let C = A+(A-B)*fA/(fB-fA);
+            let fC = f(C);
+            if fC*fB <= 0.0 {
+                A = B;
+                fA = fB;
+            } else {
+                fA = fA/2.0;
+            }
+            B = C;
+            fB = fC;
+        }
+        let new_sigma = (A/2.0).exp();
+        let phi_star = (phi*phi + new_sigma*new_sigma).sqrt();
+        let new_phi = 1.0/(1.0/(phi_star*phi_star) + 1.0/v).sqrt();
+        let new_mu = mu + new_phi*new_phi*sum;
+        Glicko2Rating {
+            rating: (new_mu*GLICKO2_SCALE) as f32,
+            rd: (new_phi*GLICKO2_SCALE).min(GLICKO2_DEFAULT_RD as f64) as f32,
+            vol: new_sigma as f32,
+        }
+    }
+    // the opposing team is folded into one composite player
+    pub fn composite(team: &Vec<Glicko2Rating>) -> Glicko2Rating {
+        let n = team.len() as f32;
+        let rating = team.iter().map(|x| x.rating).sum::<f32>() / n;
+        let rd = (team.iter().map(|x| x.rd*x.rd).sum::<f32>() / n).sqrt();
+        let vol = team.iter().map(|x| x.vol).sum::<f32>() / n;
+        Glicko2Rating {rating, rd, vol}
+    }
+    pub fn compute_glicko2_team(&self, winteam: &Vec<Glicko2Rating>, loseteam: &Vec<Glicko2Rating>)
+        -> (Vec<Glicko2Rating>, Vec<Glicko2Rating>) {
+        let win = Glicko2::composite(winteam);
+        let lose = Glicko2::composite(loseteam);
+        let mut wint = vec![];
+        let mut loset = vec![];
+        for r in winteam {
+            wint.push(self.rating(r, &vec![(lose.clone(), 1.0)]));
+        }
+        for r in loseteam {
+            loset.push(self.rating(r, &vec![(win.clone(), 0.0)]));
+        }
+        (wint, loset)
+    }
+    // teams in placement order, every player is rated against the composite
+    // of each other team. Two teams match compute_glicko2_team.
+    pub fn compute_glicko2_placement(&self, teams: &Vec<Vec<Glicko2Rating>>)
+        -> Vec<Vec<Glicko2Rating>> {
+        let composite: Vec<Glicko2Rating> = teams.iter().map(|t| Glicko2::composite(t)).collect();
+        let mut res = vec![];
+        for (i, team) in teams.iter().enumerate() {
+            let games: Vec<(Glicko2Rating, f32)> = composite.iter().enumerate()
+                .filter(|(t, _)| *t != i)
+                .map(|(t, c)| (c.clone(), if t > i { 1.0 } else { 0.0 }))
+                .collect();
+            res.push(team.iter().map(|r| self.rating(r, &games)).collect());
+        }
+        res
+    }
+}
+
+impl Calibration {
+    // p is the predicted probability of the team that won or lost by win
+    pub fn add(&mut self, p: f32, win: bool) {
+        let p = (p as f64).max(1e-6).min(1.0-1e-6);
+        let actual = if win { 1.0 } else { 0.0 };
+        self.games += 1;
+        if (p > 0.5) == win {
+            self.correct += 1;
+        }
+        self.brier_sum += (p-actual)*(p-actual);
+        self.log_loss_sum -= actual*p.ln() + (1.0-actual)*(1.0-p).ln();
+        if self.buckets.len() == 0 {
+            self.buckets = vec![Default::default(); CALIBRATION_BUCKETS];
+        }
+        for (p, win) in vec![(p, win), (1.0-p, !win)] {
+            let b = &mut self.buckets[((p*CALIBRATION_BUCKETS as f64) as usize).min(CALIBRATION_BUCKETS-1)];
+            b.count += 1;
+            b.predicted += p;
+            if win {
+                b.wins += 1;
+            }
+        }
+    }
+    pub fn brier(&self) -> f64 {
+        self.brier_sum/self.games.max(1) as f64
+    }
+    pub fn log_loss(&self) -> f64 {
+        self.log_loss_sum/self.games.max(1) as f64
+    }
+    pub fn accuracy(&self) -> f64 {
+        self.correct as f64/self.games.max(1) as f64
+    }
+}
+
+impl TrueSkill {
+    // probability of team a beating team b
+    pub fn get_expected(&self, a: &Vec<(SkillRating, f32)>, b: &Vec<(SkillRating, f32)>) -> f32 {
+        let mu = |t: &Vec<(SkillRating, f32)>| -> f32 { t.iter().map(|(r, w)| r.mu*w).sum() };
+        let sigma2 = |t: &Vec<(SkillRating, f32)>| -> f32 { t.iter().map(|(r, w)| r.sigma*r.sigma*w*w).sum() };
+        let c = (sigma2(a) + sigma2(b) + 2.0*self.beta*self.beta).sqrt();
+        1.0/(1.0+((mu(b)-mu(a))/c).exp())
+    }
+    // teams hold (rating, weight) per player, weight is the fraction of the game played.
+    // ranks: lower is better, equal ranks are a draw.
+    pub fn rate(&self, teams: &Vec<Vec<(SkillRating, f32)>>, ranks: &Vec<usize>) -> Vec<Vec<SkillRating>> {
+        let beta2 = (self.beta*self.beta) as f64;
+        let mut team_mu: Vec<f64> = vec![];
+        let mut team_sigma2: Vec<f64> = vec![];
+        for team in teams {
+            team_mu.push(team.iter().map(|(r, w)| (r.mu*w) as f64).sum());
+            team_sigma2.push(team.iter().map(|(r, w)| (r.sigma*r.sigma*w*w) as f64).sum());
+        }
+        let mut res = vec![];
+        for i in 0..teams.len() {
+            let mut omega = 0.0;
+            let mut delta = 0.0;
+            for q in 0..teams.len() {
+                if q == i {
+                    continue;
+                }
+                let c = (team_sigma2[i] + team_sigma2[q] + 2.0*beta2).sqrt();
+                let p = 1.0/(1.0+((team_mu[q]-team_mu[i])/c).exp());
+                let s = if ranks[q] > ranks[i] {
+                    1.0
+                } else if ranks[q] == ranks[i] {
+                    0.5
+                } else {
+                    0.0
+                };
+                let gamma = team_sigma2[i].sqrt()/c;
+                omega += team_sigma2[i]/c*(s-p);
+                delta += gamma*team_sigma2[i]/(c*c)*p*(1.0-p);
+            }
+            let mut team = vec![];
+            for (r, w) in &teams[i] {
+                let sigma2 = (r.sigma*r.sigma) as f64;
+                // each player takes the share of the team update that matches their uncertainty
+                let share = if team_sigma2[i] > 0.0 { *w as f64*sigma2/team_sigma2[i] } else { 0.0 };
+                let mu = r.mu as f64 + share*omega;
+                let sigma = (sigma2*(1.0-share*delta).max(TRUESKILL_KAPPA as f64)).sqrt();
+                team.push(SkillRating {mu: mu as f32, sigma: sigma as f32});
+            }
+            res.push(team);
+        }
+        res
     }
 }
 
@@ -93,4 +590,177 @@ mod tests {
         let rt = elo.compute_elo_battle_ground(&wint, 4, 0.6);
         println!("battle 0.6 {:?} \n =>        {:?}", wint, rt);
     }
+
+    #[test]
+    fn test_glicko2() {
+        // example from Glickman's "Example of the Glicko-2 system"
+        let g = Glicko2 {tau: 0.5};
+        let p = Glicko2Rating {rating: 1500.0, rd: 200.0, vol: 0.06};
+        let games = vec![
+            (Glicko2Rating {rating: 1400.0, rd: 30.0, vol: 0.06}, 1.0),
+            (Glicko2Rating {rating: 1550.0, rd: 100.0, vol: 0.06}, 0.0),
+            (Glicko2Rating {rating: 1700.0, rd: 300.0, vol: 0.06}, 0.0),
+        ];
+        let r = g.rating(&p, &games);
+        println!("glicko2 {:?}", r);
+        assert!((r.rating - 1464.06).abs() < 0.1);
+        assert!((r.rd - 151.52).abs() < 0.1);
+        assert!((r.vol - 0.05999).abs() < 0.0001);
+
+        let wint = vec![Glicko2Rating {rating: 1000.0, rd: 350.0, vol: 0.06}; 5];
+        let loset = vec![Glicko2Rating {rating: 1000.0, rd: 60.0, vol: 0.06}; 5];
+        let (wt, lt) = g.compute_glicko2_team(&wint, &loset);
+        println!("glicko2 team {:?}\n => {:?}", wt[0], lt[0]);
+        assert!(wt[0].rating - 1000.0 > 1000.0 - lt[0].rating);
+    }
+
+    #[test]
+    fn test_trueskill() {
+        let ts = TrueSkill {beta: 175.0};
+        let r = |mu: f32, sigma: f32| (SkillRating {mu, sigma}, 1.0);
+        // strong player carrying a weak party against an even team
+        let teams = vec![
+            vec![r(1400.0, 100.0), r(800.0, 100.0)],
+            vec![r(1100.0, 100.0), r(1100.0, 100.0)],
+            vec![r(1000.0, 350.0), r(1000.0, 350.0)],
+        ];
+        let res = ts.rate(&teams, &vec![0, 1, 2]);
+        println!("trueskill {:?}", res);
+        assert!(res[0][0].mu > 1400.0 && res[0][1].mu > 800.0);
+        assert!(res[2][0].mu < 1000.0);
+        assert!(res[2][0].sigma < 350.0);
+        // new players move further than settled ones
+        assert!(1000.0 - res[2][0].mu > res[0][0].mu - 1400.0);
+
+        let mut partial = teams.clone();
+        partial[0][1].1 = 0.5;
+        let res2 = ts.rate(&partial, &vec![0, 1, 2]);
+        assert!(res2[0][1].mu - 800.0 < res[0][1].mu - 800.0);
+    }
+
+    #[test]
+    fn test_placement() {
+        let elo = EloRank {k: 20.0};
+        let wint = vec![1200,1210,1190];
+        let loset = vec![1150,1130,1120];
+        let k = vec![vec![20.0; 3], vec![32.0; 3]];
+        let (wt, lt) = elo.compute_elo_team_k(&wint, &loset, &k[0], &k[1]);
+        let rt = elo.compute_elo_placement_k(&vec![wint.clone(), loset.clone()], &k);
+        assert_eq!(rt, vec![wt, lt]);
+
+        // four even teams, first gains the most and last loses the most
+        let teams = vec![vec![1000, 1000]; 4];
+        let rt = elo.compute_elo_placement_k(&teams, &vec![vec![20.0; 2]; 4]);
+        let delta: Vec<i32> = rt.iter().map(|t| t[0]-1000).collect();
+        assert_eq!(delta, vec![10, 3, -3, -10]);
+        assert_eq!(delta.iter().sum::<i32>(), 0);
+        // a single team is left as it is
+        assert_eq!(elo.compute_elo_placement_k(&vec![vec![1000, 1100]], &vec![vec![20.0; 2]]), vec![vec![1000, 1100]]);
+        assert_eq!(elo.get_expected_placement(1000.0, &vec![1000.0], 0), 0.5);
+
+        let rt = elo.compute_elo_battle_ground(&vec![1000; 4], 2, 0.5);
+        assert_eq!(rt.iter().sum::<i32>(), 4000);
+        assert!(rt[0] > rt[1] && rt[2] > rt[3]);
+        // a player on a higher K moves further than the teammate
+        let (rt, es) = elo.compute_elo_battle_ground_k(&vec![vec![1000; 2]; 4], 2, 0.5, &vec![vec![20.0, 40.0]; 4]);
+        assert_eq!(es, vec![Decimal::new(5, 1); 4]);
+        assert_eq!(rt[0][1]-1000, 2*(rt[0][0]-1000));
+        assert_eq!(rt[3][1]-1000, 2*(rt[3][0]-1000));
+
+        let g = Glicko2 {tau: 0.5};
+        let wint = vec![Glicko2Rating {rating: 1100.0, rd: 80.0, vol: 0.06}; 2];
+        let loset = vec![Glicko2Rating {rating: 1000.0, rd: 60.0, vol: 0.06}; 2];
+        let (wt, lt) = g.compute_glicko2_team(&wint, &loset);
+        let rt = g.compute_glicko2_placement(&vec![wint.clone(), loset.clone()]);
+        assert_eq!(rt, vec![wt, lt]);
+        let rt = g.compute_glicko2_placement(&vec![wint.clone(), loset.clone(), wint.clone()]);
+        assert!(rt[0][0].rating > 1100.0 && rt[2][0].rating < 1100.0);
+    }
+
+    #[test]
+    fn test_calibration() {
+        let mut c = Calibration::default();
+        c.add(0.5, true);
+        c.add(0.75, true);
+        c.add(0.75, false);
+        assert_eq!(c.games, 3);
+        assert_eq!(c.correct, 1);
+        assert!((c.brier() - (0.25+0.0625+0.5625)/3.0).abs() < 1e-6);
+        // 0.75 predicted twice with one win, seen from the other side as 0.25
+        assert_eq!(c.buckets[7].count, 2);
+        assert_eq!(c.buckets[7].wins, 1);
+        assert_eq!(c.buckets[2].count, 2);
+        assert_eq!(c.buckets.iter().map(|b| b.count).sum::<u32>(), 6);
+    }
+
+    #[test]
+    fn test_fixed() {
+        let elo = EloRank {k: 20.0};
+        assert_eq!(elo.get_expected_fixed(Decimal::from(1000), Decimal::from(1000)), Decimal::new(5, 1));
+        // 1/(1+10^-1) to RATING_DP places
+        assert_eq!(elo.get_expected_fixed(Decimal::from(1400), Decimal::from(1000)), Decimal::new(90909091, 8));
+        assert_eq!(elo.get_expected_fixed(Decimal::from(1000), Decimal::from(1400)), Decimal::new(9090909, 8));
+        assert_eq!(round_points(Decimal::new(25, 1)), 3);
+        assert_eq!(round_points(Decimal::new(-25, 1)), -3);
+        assert_eq!(apply_ratio(-25, 0.5), -13);
+        assert_eq!(apply_ratio(17, 1.1), 19);
+        assert_eq!(to_fixed(0.1), Decimal::new(1, 1));
+    }
+
+    #[test]
+    fn test_hero() {
+        let h = HeroCfg {k: 20.0, new: 50, max: 60};
+        assert_eq!(h.update(-h.new, 0.5, true), -40);
+        assert_eq!(h.update(-40, 0.25, false), -45);
+        assert_eq!(h.update(55, 0.5, true), 60);
+        assert_eq!(h.update(-58, 0.5, false), -60);
+    }
+
+    #[test]
+    fn test_streak() {
+        let s = StreakCfg {min: 3, bonus: 0.1, max: 0.3};
+        assert_eq!(s.multiplier(-4), 1.0);
+        assert_eq!(s.multiplier(2), 1.0);
+        assert_eq!(s.multiplier(3), 1.1);
+        assert_eq!(apply_ratio(20, s.multiplier(4)), 24);
+        assert_eq!(s.multiplier(10), 1.3);
+    }
+
+    #[test]
+    fn test_k_schedule() {
+        let ks = KSchedule {k: 20.0, k_new: 32.0, new_games: 30, k_high: 12.0, high_score: 1600,
+            k_provisional: 40.0, provisional_games: 10};
+        assert_eq!(ks.get_k(0, 1000), 40.0);
+        assert_eq!(ks.get_k(10, 1700), 32.0);
+        assert_eq!(ks.get_k(30, 1000), 20.0);
+        assert_eq!(ks.get_k(30, 1600), 12.0);
+        let elo = EloRank {k: 20.0};
+        let (rw, rl) = elo.compute_elo_team_k(&vec![1000, 1000], &vec![1000, 1000], &vec![40.0, 20.0], &vec![12.0, 20.0]);
+        assert_eq!(rw, vec![1020, 1010]);
+        assert_eq!(rl, vec![994, 990]);
+    }
+
+    #[test]
+    fn test_performance() {
+        let p = PerformanceCfg {damage: 1.0, be_damage: 0.0, kill: 0.0, death: 0.0, assist: 0.0, battle_score: 0.0,
+            strength: 0.5, min: 0.8, max: 1.2};
+        let s = |damage: f32| PerformanceStats {damage, ..Default::default()};
+        let team = vec![s(3000.0), s(1000.0), s(1000.0), s(1000.0)];
+        let win = p.modifier(&team, true);
+        let lose = p.modifier(&team, false);
+        println!("performance {:?} {:?}", win, lose);
+        assert_eq!(win[0], 1.2);
+        assert_eq!(lose[0], 0.8);
+        assert!(win[1] < 1.0 && lose[1] > 1.0);
+    }
+
+    #[test]
+    fn test_mmr() {
+        let m = MmrCfg {gain: 1.0, loss: 1.2, converge: 0.1};
+        assert_eq!(m.visible_delta(1000, 1000, 20, true), 22);
+        assert_eq!(m.visible_delta(1000, 1000, -20, false), -26);
+        // visible far above mmr: a win still never costs points
+        assert_eq!(m.visible_delta(1500, 1000, 10, true), 0);
+        assert_eq!(m.visible_delta(1000, 1300, -10, false), 0);
+    }
 }
@@ -32,7 +32,7 @@ pub fn login(id: String, v: Value, pool: mysql::Pool, sender: Sender<RoomEventDa
     let data: LoginData = serde_json::from_value(v)?;
     let mut rk: BTreeMap<String, ScoreInfo> = BTreeMap::new();
     for m in modes {
-        rk.insert(m.clone(), ScoreInfo{score: 1000, WinCount: 0, LoseCount: 0});
+        rk.insert(m.clone(), Default::default());
     }
     sender.send(RoomEventData::Login(UserLoginData {u: User { id: id.clone(), hero: "default name".to_string(), honor: 50, online: true, rank: rk,
         ..Default::default()}, dataid: data.id}));
@@ -13,7 +13,7 @@ use ::futures::Future;
 use mysql;
 use std::sync::{Arc, Mutex, Condvar, RwLock};
 use crossbeam_channel::{bounded, tick, Sender, Receiver, select};
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, BTreeMap, BTreeSet};
 use std::cell::RefCell;
 use std::rc::Rc;
 use std::fs::File;
@@ -21,20 +21,33 @@ use std::io::prelude::*;
 use uuid::Uuid;
 use failure::Error;
 use rust_decimal::Decimal;
+use rust_decimal::prelude::ToPrimitive;
 use rust_decimal_macros::*;
 
 extern crate toml;
 use crate::room::*;
 use crate::msg::*;
 use crate::elo::*;
+use crate::review::*;
+use crate::queue::{QueueIndex, WidenCfg, HonorCfg};
+use crate::leaderboard::*;
 use std::process::Command;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct GameSetting {
-    pub SCORE_INTERVAL: Option<i16>,
+    pub SCORE_INTERVAL: Option<i32>,
     pub HONOR_THRESHOLD: Option<i32>,
+    // ascending honor thresholds, below the first is the low priority queue
+    pub HONOR_TIERS: Option<Vec<i32>>,
+    pub LOW_PRIORITY_DELAY: Option<i64>,
+    pub HONOR_MERGE_WAIT: Option<i64>,
     pub BLOCK_RECENT_PLAYER_OF_GAMES: Option<usize>,
     pub HERO: Option<Vec<String>>,
+    pub LEADERBOARD_SIZE: Option<usize>,
+    pub LEADERBOARD_SNAPSHOT_INTERVAL: Option<u64>,
+    pub CALIBRATION_REPORT_INTERVAL: Option<u64>,
+    // seconds a game waits for the other of game over and game info before settling without it
+    pub SETTLE_WAIT: Option<i64>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -42,19 +55,188 @@ pub struct GameMode {
     pub MODE: Option<String>,
     pub TEAM_SIZE: Option<i16>,
     pub MATCH_SIZE: Option<usize>,
-    pub SCORE_INTERVAL: Option<i16>,
+    pub SCORE_INTERVAL: Option<i32>,
+    pub MATCH_WINDOW: Option<i32>,
+    pub MATCH_WINDOW_GROWTH: Option<f32>,
+    pub MATCH_WINDOW_CAP: Option<i32>,
+    pub BALANCE_TEAMS: Option<bool>,
     pub BLOCK_RECENT_PLAYER_OF_GAMES: Option<usize>,
+    pub RATING: Option<String>,
+    pub GLICKO2_TAU: Option<f32>,
+    pub TRUESKILL_BETA: Option<f32>,
+    pub K: Option<f32>,
+    pub K_NEW: Option<f32>,
+    pub K_NEW_GAMES: Option<u32>,
+    pub K_HIGH: Option<f32>,
+    pub K_HIGH_SCORE: Option<i32>,
+    pub K_PROVISIONAL: Option<f32>,
+    pub PROVISIONAL_GAMES: Option<u32>,
+    pub PLACEMENT_GAMES: Option<u32>,
+    pub DECAY_THRESHOLD: Option<i32>,
+    pub DECAY_DAYS: Option<u32>,
+    pub DECAY_POINTS: Option<i32>,
+    pub DECAY_FLOOR: Option<i32>,
+    pub PERF_MODIFIER: Option<bool>,
+    pub PERF_DAMAGE: Option<f32>,
+    pub PERF_BE_DAMAGE: Option<f32>,
+    pub PERF_KILL: Option<f32>,
+    pub PERF_DEATH: Option<f32>,
+    pub PERF_ASSIST: Option<f32>,
+    pub PERF_BATTLESCORE: Option<f32>,
+    pub PERF_STRENGTH: Option<f32>,
+    pub PERF_MIN: Option<f32>,
+    pub PERF_MAX: Option<f32>,
+    pub MMR_GAIN: Option<f32>,
+    pub MMR_LOSS: Option<f32>,
+    pub MMR_CONVERGE: Option<f32>,
+    pub FFA_RATING: Option<String>,
+    pub BATTLE_GROUND_SCALE: Option<f32>,
+    pub LEAVER_PENALTY: Option<i32>,
+    pub LEAVER_MITIGATION: Option<f32>,
+    pub LEAVER_HONOR: Option<i32>,
+    pub LEAVER_BAN: Option<u64>,
+    pub LEAVER_RESET_DAYS: Option<u32>,
+    pub STREAK_MIN: Option<i32>,
+    pub STREAK_BONUS: Option<f32>,
+    pub STREAK_MAX: Option<f32>,
+    pub SHIELD_GAMES: Option<u32>,
+    pub REVIEW_PAIR_GAMES: Option<u32>,
+    pub REVIEW_WINDOW_DAYS: Option<u32>,
+    pub REVIEW_WIN_RATE: Option<f32>,
+    pub REVIEW_WIN_GAMES: Option<u32>,
+    pub REVIEW_SHORT_GAME: Option<i64>,
+    pub REVIEW_SHORT_GAMES: Option<u32>,
+    pub REVIEW_FREEZE: Option<bool>,
+    pub HERO_K: Option<f32>,
+    pub HERO_NEW: Option<i32>,
+    pub HERO_MAX: Option<i32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankTier {
+    pub NAME: Option<String>,
+    pub SCORE: Option<i32>,
+    pub DIVISIONS: Option<u16>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct Season {
+    pub ID: Option<u32>,
+    pub NAME: Option<String>,
+    pub START: Option<String>,
+    pub END: Option<String>,
+    pub SOFT_RESET_TARGET: Option<i32>,
+    pub SOFT_RESET_RATIO: Option<f32>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct Config {
     pub game_setting: Option<GameSetting>,
     pub game_mode: Option<Vec<GameMode>>,
+    pub rank_tier: Option<Vec<RankTier>>,
+    pub season: Option<Vec<Season>>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeasonCfg {
+    pub id: u32,
+    pub name: String,
+    // unix time, dates in config are UTC "%Y-%m-%d"
+    pub start: i64,
+    pub end: i64,
+    pub target: i32,
+    pub ratio: f32,
+}
+
+impl SeasonCfg {
+    // pull the score toward target by ratio
+    pub fn soft_reset(&self, score: i32) -> i32 {
+        score + apply_ratio(self.target - score, self.ratio)
+    }
+}
+
+pub fn get_season_cfg(season: Vec<Season>) -> Vec<SeasonCfg> {
+    let date = |d: &String| -> i64 {
+        match time::strptime(d, "%Y-%m-%d") {
+            Ok(t) => t.to_timespec().sec,
+            Err(e) => panic!("season date {} exception:{}", d, e)
+        }
+    };
+    let mut seasons: Vec<SeasonCfg> = vec![];
+    for x in season {
+        seasons.push(SeasonCfg {
+            id: x.ID.unwrap(),
+            name: x.NAME.clone().unwrap_or_default(),
+            start: date(&x.START.clone().unwrap()),
+            end: date(&x.END.clone().unwrap()),
+            target: x.SOFT_RESET_TARGET.unwrap_or(1000),
+            ratio: x.SOFT_RESET_RATIO.unwrap_or(0.5),
+        });
+    }
+    seasons.sort_by_key(|x| x.end);
+    // seasons follow each other, so at most one is current at a time
+    for (i, x) in seasons.iter().enumerate() {
+        if x.start >= x.end || (i > 0 && x.start < seasons[i-1].end) {
+            panic!("season {} overlaps or ends before it starts", x.id);
+        }
+    }
+    seasons
+}
+
+pub fn current_season(seasons: &Vec<SeasonCfg>, now: i64) -> Option<&SeasonCfg> {
+    seasons.iter().find(|x| x.start <= now && now < x.end)
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RankTierCfg {
+    pub name: String,
+    pub score: i32,
+    pub divisions: u16,
+}
+
+pub fn get_tier_cfg(rank_tier: Vec<RankTier>) -> Vec<RankTierCfg> {
+    let mut tiers: Vec<RankTierCfg> = vec![];
+    for x in rank_tier {
+        tiers.push(RankTierCfg {
+            name: x.NAME.clone().unwrap(),
+            score: x.SCORE.unwrap(),
+            divisions: x.DIVISIONS.unwrap_or(1).max(1),
+        });
+    }
+    tiers.sort_by_key(|x| x.score);
+    tiers
+}
+
+// lowest score of the tier the score is in
+pub fn get_tier_floor(tiers: &Vec<RankTierCfg>, score: i32) -> i32 {
+    tiers.iter().rev().find(|t| score >= t.score).map(|t| t.score).unwrap_or(i32::min_value())
+}
+
+// tier name and division of a score, division 1 is the top of the tier
+pub fn get_tier(tiers: &Vec<RankTierCfg>, score: i32) -> (String, u16) {
+    let mut idx = 0;
+    for (i, t) in tiers.iter().enumerate() {
+        if score >= t.score {
+            idx = i;
+        }
+    }
+    match tiers.get(idx) {
+        Some(t) => {
+            let mut division = 1;
+            if let Some(next) = tiers.get(idx+1) {
+                let step = ((next.score - t.score) as f32 / t.divisions as f32).max(1.0);
+                let n = (((score - t.score).max(0)) as f32 / step) as u16;
+                division = t.divisions - n.min(t.divisions-1);
+            }
+            (t.name.clone(), division)
+        }
+        None => ("".to_string(), 0),
+    }
 }
 
 const TEAM_SIZE: i16 = 5;
 const MATCH_SIZE: usize = 2;
-const SCORE_INTERVAL: i16 = 100;
+const SCORE_INTERVAL: i32 = 100;
 const BLOCK_RECENT_PLAYER_OF_GAMES: usize = 2;
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -62,6 +244,190 @@ pub struct GameModeCfg {
     pub mode: String,
     pub team_size: i16,
     pub match_size: usize,
+    pub rating: String,
+    pub tau: f32,
+    pub beta: f32,
+    pub k: KSchedule,
+    // games shown as unranked. only elo modes rate them with k_provisional,
+    // glicko2 and trueskill move fast there through the starting deviation
+    pub placement_games: u32,
+    pub decay: Option<DecayCfg>,
+    pub perf: Option<PerformanceCfg>,
+    pub mmr: MmrCfg,
+    // elo with more than two teams: "pairwise" or "battle_ground"
+    pub ffa: String,
+    pub battle_scale: f32,
+    pub leaver: Option<LeaverCfg>,
+    pub streak: Option<StreakCfg>,
+    // losses covered by the demotion shield after a promotion to a new tier, 0 is off
+    pub shield_games: u32,
+    pub review: Option<ReviewCfg>,
+    pub hero: Option<HeroCfg>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct DecayCfg {
+    pub threshold: i32,
+    pub days: u32,
+    pub points: i32,
+    pub floor: i32,
+}
+
+impl DecayCfg {
+    // decay of every full period without a game, returns the points lost
+    pub fn apply(&self, info: &mut ScoreInfo, now: i64) -> i32 {
+        let period = self.days as i64 * 86400;
+        let base = info.last_game.max(info.last_decay);
+        if base == 0 {
+            // rows from before decay existed start their clock now
+            info.last_decay = now;
+            return 0;
+        }
+        if period == 0 || now - base < period || info.score <= self.threshold {
+            return 0;
+        }
+        let periods = (now - base) / period;
+        info.last_decay = base + periods * period;
+        let score = (info.score as i64 - self.points as i64 * periods).max(self.floor as i64) as i32;
+        let lost = (info.score - score).max(0);
+        info.score -= lost;
+        info.decayed += lost;
+        lost
+    }
+}
+
+// penalties for players the game server flags as afk or abandoned
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaverCfg {
+    // extra rating lost by the leaver
+    pub penalty: i32,
+    // fraction of the loss taken off the leaver's teammates
+    pub mitigation: f32,
+    pub honor: i32,
+    // queue ban in seconds, multiplied by the leaves within reset_days
+    pub ban: u64,
+    pub reset_days: u32,
+}
+
+impl LeaverCfg {
+    // honor deduction and escalating queue ban, returns the ban added in seconds
+    pub fn apply(&self, u: &mut User, now: i64) -> u64 {
+        if self.reset_days > 0 && now - u.last_leave >= self.reset_days as i64 * 86400 {
+            u.leaves = 0;
+        }
+        u.leaves += 1;
+        u.last_leave = now;
+        u.honor -= self.honor;
+        let ban = self.ban * u.leaves as u64;
+        if Instant::now().duration_since(u.ban.from) > u.ban.long {
+            u.ban.from = Instant::now();
+            u.ban.long = Duration::new(ban, 0);
+        } else {
+            u.ban.long += Duration::new(ban, 0);
+        }
+        ban
+    }
+}
+
+impl GameModeCfg {
+    pub fn new(x: &GameMode) -> GameModeCfg {
+        GameModeCfg {
+            mode: x.MODE.clone().unwrap(),
+            team_size: x.TEAM_SIZE.unwrap(),
+            match_size: x.MATCH_SIZE.unwrap(),
+            rating: x.RATING.clone().unwrap_or("elo".to_owned()),
+            tau: x.GLICKO2_TAU.unwrap_or(0.5),
+            beta: x.TRUESKILL_BETA.unwrap_or(GLICKO2_DEFAULT_RD/2.0),
+            k: KSchedule {
+                k: x.K.unwrap_or(20.0),
+                k_new: x.K_NEW.or(x.K).unwrap_or(20.0),
+                new_games: x.K_NEW_GAMES.unwrap_or(0),
+                k_high: x.K_HIGH.or(x.K).unwrap_or(20.0),
+                high_score: x.K_HIGH_SCORE.unwrap_or(std::i32::MAX),
+                k_provisional: x.K_PROVISIONAL.or(x.K).unwrap_or(20.0),
+                provisional_games: x.PROVISIONAL_GAMES.unwrap_or(0),
+            },
+            placement_games: x.PLACEMENT_GAMES.unwrap_or(0),
+            decay: match x.DECAY_DAYS {
+                Some(days) => Some(DecayCfg {
+                    threshold: x.DECAY_THRESHOLD.unwrap_or(0),
+                    days: days,
+                    points: x.DECAY_POINTS.unwrap_or(0),
+                    floor: x.DECAY_FLOOR.unwrap_or(x.DECAY_THRESHOLD.unwrap_or(0)),
+                }),
+                None => None,
+            },
+            perf: if x.PERF_MODIFIER.unwrap_or(false) {
+                Some(PerformanceCfg {
+                    damage: x.PERF_DAMAGE.unwrap_or(1.0),
+                    be_damage: x.PERF_BE_DAMAGE.unwrap_or(0.0),
+                    kill: x.PERF_KILL.unwrap_or(0.0),
+                    death: x.PERF_DEATH.unwrap_or(0.0),
+                    assist: x.PERF_ASSIST.unwrap_or(0.0),
+                    battle_score: x.PERF_BATTLESCORE.unwrap_or(0.0),
+                    strength: x.PERF_STRENGTH.unwrap_or(0.25),
+                    min: x.PERF_MIN.unwrap_or(0.8),
+                    max: x.PERF_MAX.unwrap_or(1.2),
+                })
+            } else {
+                None
+            },
+            mmr: MmrCfg {
+                gain: x.MMR_GAIN.unwrap_or(1.0),
+                loss: x.MMR_LOSS.unwrap_or(1.0),
+                converge: x.MMR_CONVERGE.unwrap_or(0.0),
+            },
+            ffa: x.FFA_RATING.clone().unwrap_or("pairwise".to_owned()),
+            battle_scale: x.BATTLE_GROUND_SCALE.unwrap_or(0.5),
+            leaver: match x.LEAVER_PENALTY {
+                Some(penalty) => Some(LeaverCfg {
+                    penalty: penalty,
+                    mitigation: x.LEAVER_MITIGATION.unwrap_or(0.0),
+                    honor: x.LEAVER_HONOR.unwrap_or(0),
+                    ban: x.LEAVER_BAN.unwrap_or(0),
+                    reset_days: x.LEAVER_RESET_DAYS.unwrap_or(0),
+                }),
+                None => None,
+            },
+            streak: match x.STREAK_BONUS {
+                Some(bonus) => Some(StreakCfg {
+                    min: x.STREAK_MIN.unwrap_or(2),
+                    bonus: bonus,
+                    max: x.STREAK_MAX.unwrap_or(bonus),
+                }),
+                None => None,
+            },
+            shield_games: x.SHIELD_GAMES.unwrap_or(0),
+            review: match x.REVIEW_PAIR_GAMES {
+                Some(pair_games) => Some(ReviewCfg {
+                    window: x.REVIEW_WINDOW_DAYS.unwrap_or(7) as i64 * 86400,
+                    pair_games: pair_games,
+                    win_rate: x.REVIEW_WIN_RATE.unwrap_or(1.0),
+                    win_games: x.REVIEW_WIN_GAMES.unwrap_or(pair_games),
+                    short_game: x.REVIEW_SHORT_GAME.unwrap_or(0),
+                    short_games: x.REVIEW_SHORT_GAMES.unwrap_or(1),
+                    freeze: x.REVIEW_FREEZE.unwrap_or(false),
+                }),
+                None => None,
+            },
+            hero: match x.HERO_K {
+                Some(k) => Some(HeroCfg {
+                    k: k,
+                    new: x.HERO_NEW.unwrap_or(0),
+                    max: x.HERO_MAX.unwrap_or(i32::max_value()),
+                }),
+                None => None,
+            },
+        }
+    }
+    // settlement waits for game info when it needs the per player stats or afk flags
+    pub fn settle_on_info(&self) -> bool {
+        self.perf.is_some() || self.leaver.is_some()
+    }
+    // score of a player who has not played this mode yet
+    pub fn new_score(&self) -> ScoreInfo {
+        ScoreInfo {placement: self.placement_games, ..Default::default()}
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -116,7 +482,11 @@ pub struct UserLogoutData {
 pub struct StartQueueData {
     pub id: String,
     pub action: String,
+    #[serde(default)]
     pub mode: String,
+    // queue for all of these modes at once, the first match wins
+    #[serde(default)]
+    pub modes: Vec<String>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
@@ -210,6 +580,10 @@ pub struct GameOverData {
     pub game: u32,  
     pub win: Vec<String>,
     pub lose: Vec<String>,
+    // fraction of the game each player was in, missing players count as 1.0
+    pub weight: Option<BTreeMap<String, f32>>,
+    // teams from first to last place, used instead of win and lose when set
+    pub rank: Option<Vec<Vec<String>>>,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -264,6 +638,28 @@ pub struct UserInfoRes {
     pub RankScore: u32,
 }
 
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ScoreRes {
+    pub mode: String,
+    pub last_score: i32,
+    pub score: i32,
+    pub delta: i32,
+    pub WinCount: u32,
+    pub LoseCount: u32,
+    pub placement: u32,
+    pub ranked: bool,
+    pub tier: String,
+    pub division: u16,
+    pub streak: i32,
+    // points added by the win streak
+    pub streak_bonus: i32,
+    // demotion shield losses left, shielded is set when it held this game
+    pub shield: u32,
+    pub shielded: bool,
+    // the rating is frozen pending review
+    pub frozen: bool,
+}
+
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
 pub struct GetRPData {
     pub id: String,
@@ -296,6 +692,8 @@ pub struct UserInfoData {
     pub Talent: UserGift,
     pub BattleScore: String,
     pub Currency: u32,
+    #[serde(default)]
+    pub afk: bool,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Clone, Debug)]
@@ -487,6 +885,11 @@ pub enum RoomEventData {
     DeleteOption(DeleteOption),
     AddBlackList(Black),
     QueryBlackList(QueryBlack),
+    SeasonStandings(SeasonStandingsData),
+    RatingHistory(RatingHistoryData),
+    LeaderboardQuery(LeaderboardQueryData),
+    ReviewList(ReviewListData),
+    ReviewResolve(ReviewResolveData),
     RemoveBlackList(Black),
     TestEquip(TestData),
     Status(StatusData),
@@ -501,12 +904,218 @@ pub struct SqlLoginData {
     pub name: String,
 }
 
+#[derive(Clone, Debug, Default)]
+pub struct SqlLedgerData {
+    pub id: String,
+    pub game: u32,
+    pub mode: String,
+    pub old_score: i32,
+    pub new_score: i32,
+    pub old_mmr: i32,
+    pub new_mmr: i32,
+    pub expected: f32,
+    pub k: f32,
+    pub modifier: f32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct RatingHistoryData {
+    #[serde(default)]
+    pub id: String,
+    pub mode: Option<String>,
+    pub count: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct RatingHistoryRes {
+    pub game: u32,
+    pub old_score: i32,
+    pub new_score: i32,
+    pub expected: f32,
+    pub k: f32,
+    pub modifier: f32,
+    pub date: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewListData {
+    pub mode: Option<String>,
+    // 0 open, 1 cleared, 2 confirmed
+    pub status: Option<u8>,
+    pub count: Option<u32>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReviewRes {
+    pub no: u64,
+    pub mode: String,
+    pub id: String,
+    pub other: String,
+    pub reason: String,
+    pub games: u32,
+    pub wins: u32,
+    pub status: u8,
+    pub date: String,
+}
+
+// closing a flag lifts the freeze once the player has no other open flag in the mode,
+// confirmed only records the outcome for follow up
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewResolveData {
+    pub no: u64,
+    #[serde(default)]
+    pub confirmed: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct ReviewResolveRes {
+    pub no: u64,
+    pub msg: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct LeaderboardQueryData {
+    #[serde(default)]
+    pub mode: String,
+    pub id: String,
+    pub top: Option<usize>,
+    pub around: Option<usize>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LeaderboardRes {
+    pub mode: String,
+    pub total: usize,
+    pub position: Option<usize>,
+    pub top: Vec<LeaderboardEntry>,
+    pub around: Vec<LeaderboardEntry>,
+}
+
+#[derive(Clone, Debug)]
+pub struct SqlLeaderboardData {
+    pub mode: String,
+    pub list: Vec<LeaderboardEntry>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct LeaverRes {
+    pub game: u32,
+    pub honor: i32,
+    pub leaves: u32,
+    pub ban: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct DecayRes {
+    pub mode: String,
+    pub decayed: i32,
+    pub score: i32,
+}
+
+#[derive(Clone, Debug)]
+pub struct SqlSeasonData {
+    pub season: SeasonCfg,
+    pub modes: Vec<String>,
+    // (mode, id) of players frozen by review, their rating is not reset
+    pub frozen: Vec<(String, String)>,
+    // gets the season id back with whether the transaction committed
+    pub done: Sender<(u32, bool)>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct SeasonStandingsData {
+    #[serde(default)]
+    pub id: String,
+    pub season: u32,
+    pub mode: String,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SeasonRankRes {
+    pub id: String,
+    pub score: i32,
+    pub peak: i32,
+    pub WinCount: u32,
+    pub LoseCount: u32,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct SeasonStandingsRes {
+    pub season: u32,
+    pub mode: String,
+    pub list: Vec<SeasonRankRes>,
+    pub me: Option<SeasonRankRes>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CalibrationRes {
+    pub mode: String,
+    pub games: u32,
+    pub brier: f64,
+    pub log_loss: f64,
+    pub accuracy: f64,
+    pub buckets: Vec<CalibrationBucketRes>,
+}
+
+// predicted win probability in [low, high) against the observed win rate
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct CalibrationBucketRes {
+    pub low: f32,
+    pub high: f32,
+    pub count: u32,
+    pub predicted: f64,
+    pub won: f64,
+}
+
+impl CalibrationRes {
+    pub fn new(mode: String, c: &Calibration) -> CalibrationRes {
+        let n = CALIBRATION_BUCKETS as f32;
+        CalibrationRes {
+            mode: mode,
+            games: c.games,
+            brier: c.brier(),
+            log_loss: c.log_loss(),
+            accuracy: c.accuracy(),
+            buckets: c.buckets.iter().enumerate().filter(|(_, b)| b.count > 0).map(|(i, b)| CalibrationBucketRes {
+                low: i as f32/n,
+                high: (i+1) as f32/n,
+                count: b.count,
+                predicted: b.predicted/b.count as f64,
+                won: b.wins as f64/b.count as f64,
+            }).collect(),
+        }
+    }
+}
+
+// last run of a periodic task, kept across restarts
+#[derive(Clone, Debug)]
+pub struct SqlTaskData {
+    pub name: String,
+    pub time: i64,
+}
+
+#[derive(Clone, Debug)]
+pub struct SqlHeroRatingData {
+    pub id: String,
+    pub mode: String,
+    pub hero: String,
+    pub mmr: i32,
+    pub games: u32,
+}
+
+#[derive(Clone, Debug)]
+pub struct SqlHonorData {
+    pub id: String,
+    pub honor: i32,
+    pub leaves: u32,
+    pub last_leave: i64,
+}
+
 #[derive(Clone, Debug)]
 pub struct SqlScoreData {
     pub id: String,
-    pub score: i16,
     pub mode: String,
-    pub Res: bool,
+    pub info: ScoreInfo,
 }
 
 #[derive(Clone, Debug)]
@@ -528,6 +1137,7 @@ pub struct SqlGameData {
 pub struct SqlGameInfoData {
     pub game : u32,
     pub id: String,
+    pub mode: String,
     pub hero: String,
     pub equ: String,
     pub damage: u16, 
@@ -538,6 +1148,8 @@ pub struct SqlGameInfoData {
     pub D: u16,
     pub A: u16,
     pub BattleScore: u8,
+    // the payload's battle score the performance modifier used
+    pub battle_score: f32,
     pub Currency: u32,
     pub Talent: UserGift,
 }
@@ -556,6 +1168,16 @@ pub enum SqlData {
     AddBlack(Black),
     RemoveBlack(Black),
     HeroNum(SqlHeroname),
+    SeasonRollover(SqlSeasonData),
+    RatingLedger(SqlLedgerData),
+    LeaderboardSnapshot(SqlLeaderboardData),
+    UpdateHonor(SqlHonorData),
+    AddReview(ReviewFlag),
+    UpdateHeroRating(SqlHeroRatingData),
+    SeasonStandings(SeasonStandingsData),
+    RatingHistory(RatingHistoryData),
+    ReviewList(ReviewListData),
+    TaskRun(SqlTaskData),
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -565,14 +1187,21 @@ pub struct QueueRoomData {
     pub rid: u32,
     pub gid: u32,
     pub user_len: i16,
-    pub avg: BTreeMap<String, i16>,
-    pub honor: bool,
+    pub avg: BTreeMap<String, i32>,
+    // honor tier of the room
+    pub honor: u8,
     pub mode: String,
     pub ready: i8,
     pub allow_same_hero: bool,
     pub queue_cnt: i16,
     pub block: Vec<String>,
     pub blacklist: Vec<String>,
+    // mean hero offset of the players, added to the mode average by the queue
+    #[serde(default)]
+    pub hero_mmr: i32,
+    // unix time the room entered the queue
+    #[serde(default)]
+    pub queue_time: i64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -583,13 +1212,15 @@ pub struct ReadyGroupData {
     pub rid: Vec<u32>,
     pub max_room_len: i16,
     pub user_len: i16,
-    pub avg: BTreeMap<String, i16>,
-    pub honor: bool,
+    pub avg: BTreeMap<String, i32>,
+    pub honor: u8,
     pub allow_same_hero: bool,
     pub game_status: u16,
     pub queue_cnt: i16,
     pub block: Vec<String>,
     pub blacklist: Vec<String>,
+    // queue time of the room that has waited longest
+    pub queue_time: i64,
 }
 
 #[derive(Serialize, Deserialize, Clone, Debug, Default)]
@@ -597,7 +1228,7 @@ pub struct ReadyGameData {
     pub user_name: Vec<String>,
     pub hero: Vec<String>,
     pub gid: Vec<u32>,
-    pub honor: bool,
+    pub honor: u8,
     pub allow_same_hero: bool,
     pub group: Vec<Vec<u32>>,
     pub team_len: usize,
@@ -767,56 +1398,550 @@ fn get_users(ids: &Vec<String>, users: &BTreeMap<String, Rc<RefCell<User>>>) ->
     }
 }
 
-fn user_score(u: &Rc<RefCell<User>>, value: i16, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>, conn: &mut mysql::PooledConn, mode: String, Win: bool) -> Result<(), Error> {
-    
-    msgtx.try_send(MqttMsg{topic:format!("member/{}/res/login", u.borrow().id), 
-        msg: format!(r#"{{"msg":"ok" }}"#), ..Default::default()})?;
-    //println!("Update!");
-    let rank : &mut BTreeMap<String, ScoreInfo> = &mut u.borrow_mut().rank;
-    if (rank.contains_key(&mode)) {
-        if Win == true {
-            rank.get_mut(&mode).unwrap().WinCount += 1;
-        } else {
-            rank.get_mut(&mode).unwrap().LoseCount += 1;
+// value is the mmr delta, the visible score follows it by the mode's MmrCfg
+pub fn score_game(info: &mut ScoreInfo, value: i32, gm: &GameModeCfg, tiers: &Vec<RankTierCfg>, Win: bool) -> ScoreRes {
+    let mut res = ScoreRes {mode: gm.mode.clone(), ..Default::default()};
+    res.last_score = info.score;
+    info.streak = if Win { info.streak.max(0) + 1 } else { info.streak.min(0) - 1 };
+    if info.frozen {
+        // the game counts but the rating waits for the review
+        res.frozen = true;
+    } else if info.placement > 0 {
+        // placement shows the mmr as it is found
+        info.mmr += value;
+        info.score = info.mmr;
+    } else {
+        let mut d = gm.mmr.visible_delta(info.score, info.mmr, value, Win);
+        if let Some(streak) = &gm.streak {
+            if d > 0 {
+                res.streak_bonus = apply_ratio(d, streak.multiplier(info.streak)) - d;
+                d += res.streak_bonus;
+            }
+        }
+        let floor = get_tier_floor(tiers, info.score);
+        // only a loss uses up the shield
+        if info.shield > 0 && d < 0 {
+            if info.score + d < floor {
+                d = floor - info.score;
+                res.shielded = true;
+            }
+            info.shield -= 1;
         }
+        info.mmr += value;
+        info.score += d;
+        if gm.shield_games > 0 && get_tier_floor(tiers, info.score) > floor {
+            info.shield = gm.shield_games;
+        }
+    }
+    res.streak = info.streak;
+    res.shield = info.shield;
+    res.delta = info.score - res.last_score;
+    info.last_game = time::get_time().sec;
+    if info.score > info.peak {
+        info.peak = info.score;
+    }
+    if info.placement > 0 {
+        info.placement -= 1;
+    }
+    res.placement = info.placement;
+    res.ranked = info.placement == 0;
+    if res.ranked {
+        let (tier, division) = get_tier(tiers, info.score);
+        res.tier = tier;
+        res.division = division;
+    } else {
+        res.tier = "Unranked".to_string();
+    }
+    if Win == true {
+        info.WinCount += 1;
+        info.season_win += 1;
+    } else {
+        info.LoseCount += 1;
+        info.season_lose += 1;
+    }
+    res.score = info.score;
+    res.WinCount = info.WinCount;
+    res.LoseCount = info.LoseCount;
+    res
+}
+
+fn user_score(u: &Rc<RefCell<User>>, value: i32, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>, gm: &GameModeCfg, tiers: &Vec<RankTierCfg>, Win: bool, mut ledger: SqlLedgerData) -> Result<ScoreRes, Error> {
+    let id = u.borrow().id.clone();
+    let mode = gm.mode.clone();
+    let (res, info) = {
+        let rank : &mut BTreeMap<String, ScoreInfo> = &mut u.borrow_mut().rank;
+        let info = rank.entry(mode.clone()).or_insert(gm.new_score());
+        ledger.old_mmr = info.mmr;
+        let res = score_game(info, value, gm, tiers, Win);
+        ledger.new_mmr = info.mmr;
+        (res, info.clone())
+    };
+    u.borrow_mut().update_hidden_rank();
+    ledger.id = id.clone();
+    ledger.mode = mode.clone();
+    ledger.old_score = res.last_score;
+    ledger.new_score = res.score;
+    sender.try_send(SqlData::UpdateScore(SqlScoreData {id: id.clone(), mode: mode.clone(), info: info}))?;
+    sender.try_send(SqlData::RatingLedger(ledger))?;
+    msgtx.try_send(MqttMsg{topic:format!("member/{}/res/score", id), 
+        msg: json!(res).to_string(), ..Default::default()})?;
+    Ok(res)
+}
+
+// tell the player about decay since the last login
+fn decay_notice(u: &Rc<RefCell<User>>, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>) -> Result<(), Error> {
+    let id = u.borrow().id.clone();
+    let mut res: Vec<DecayRes> = vec![];
+    for (mode, info) in u.borrow_mut().rank.iter_mut() {
+        if info.decayed > 0 {
+            res.push(DecayRes {mode: mode.clone(), decayed: info.decayed, score: info.score});
+            info.decayed = 0;
+            sender.try_send(SqlData::UpdateScore(SqlScoreData {id: id.clone(), mode: mode.clone(), info: info.clone()}))?;
+        }
+    }
+    if res.len() > 0 {
+        msgtx.try_send(MqttMsg{topic:format!("member/{}/res/decay", id), 
+            msg: json!(res).to_string(), ..Default::default()})?;
     }
-    //let sql = format!("UPDATE user_ng as a JOIN user as b ON a.id=b.id SET score={} WHERE b.userid='{}';", u.borrow().ng, u.borrow().id);
-    //println!("sql: {}", sql);
-    //let qres = conn.query(sql.clone())?;
     Ok(())
 }
 
-fn get_rk(team : &Vec<Rc<RefCell<User>>>, mode: String) -> Vec<i32> {
-    let mut res: Vec<i32> = vec![];
+// rating rows of the mode, users without a row for this mode yet play at the new score
+fn get_infos(team : &Vec<Rc<RefCell<User>>>, gm: &GameModeCfg) -> Vec<ScoreInfo> {
+    let mut res: Vec<ScoreInfo> = vec![];
     for u in team {
         let rank : &BTreeMap<String, ScoreInfo> = &u.borrow().rank;
-        if (rank.contains_key(&mode)) {
-            res.push(rank.get(&mode).unwrap().score.into());
-        }        
+        res.push(rank.get(&gm.mode).cloned().unwrap_or(gm.new_score()));
     }
     res
 }
 
 
-fn settlement_ng_score(win: &Vec<Rc<RefCell<User>>>, lose: &Vec<Rc<RefCell<User>>>, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>, conn: &mut mysql::PooledConn, mode: String) {
-    if win.len() == 0 || lose.len() == 0 {
+fn get_k(team : &Vec<ScoreInfo>, gm: &GameModeCfg) -> Vec<f32> {
+    let mut res: Vec<f32> = vec![];
+    for info in team {
+        // placement games move the rating with the provisional K, the only
+        // placement boost of elo modes; the other algorithms never call get_k
+        if info.placement > 0 {
+            res.push(gm.k.k_provisional);
+        } else {
+            res.push(gm.k.get_k(info.WinCount + info.LoseCount, info.mmr.into()));
+        }
+    }
+    res
+}
+
+fn get_glicko2(team : &Vec<ScoreInfo>) -> Vec<Glicko2Rating> {
+    let mut res: Vec<Glicko2Rating> = vec![];
+    for info in team {
+        res.push(Glicko2Rating {rating: info.mmr as f32, rd: info.rd, vol: info.vol});
+    }
+    res
+}
+
+fn get_skill(team : &Vec<ScoreInfo>, ids: &Vec<String>, weight: &BTreeMap<String, f32>) -> Vec<(SkillRating, f32)> {
+    let mut res: Vec<(SkillRating, f32)> = vec![];
+    for (info, id) in team.iter().zip(ids) {
+        let w = weight.get(id).cloned().unwrap_or(1.0);
+        res.push((SkillRating {mu: info.mmr as f32, sigma: info.sigma}, w));
+    }
+    res
+}
+
+fn set_deviation(u: &Rc<RefCell<User>>, gm: &GameModeCfg, r: &GameRating) {
+    let rank : &mut BTreeMap<String, ScoreInfo> = &mut u.borrow_mut().rank;
+    let info = rank.entry(gm.mode.clone()).or_insert(gm.new_score());
+    // the deviation waits for the review like the rating
+    if info.frozen {
         return;
     }
-    let mut win_score: Vec<i32> = get_rk(win, mode.clone());
-    let mut lose_score: Vec<i32> = get_rk(lose, mode.clone());
+    info.rd = r.rd;
+    info.vol = r.vol;
+    info.sigma = r.sigma;
+}
+
+fn check_table(conn: &mut mysql::PooledConn, table: &str, create: &str) -> Result<(), Error> {
+    let res = {
+        let sql = format!(r#"select count(*) from information_schema.tables where TABLE_NAME='{}';"#, table);
+        let mut qres: mysql::QueryResult = conn.query(sql.clone())?;
+        let v = qres.next()
+            .unwrap()
+            .unwrap()
+            .take(0)
+            .unwrap();
+        mysql::from_value::<i32>(v)
+    };
+    if res == 0 {
+        conn.query(create)?;
+    }
+    Ok(())
+}
+
+// adds the column when it is missing, returns true if it was added
+fn check_column(conn: &mut mysql::PooledConn, table: &str, column: &str, define: &str) -> Result<bool, Error> {
+    let res = {
+        let sql = format!(r#"select count(*) from information_schema.columns where TABLE_NAME='{}' and COLUMN_NAME='{}';"#, table, column);
+        let mut qres: mysql::QueryResult = conn.query(sql.clone())?;
+        let v = qres.next()
+            .unwrap()
+            .unwrap()
+            .take(0)
+            .unwrap();
+        mysql::from_value::<i32>(v)
+    };
+    if res == 0 {
+        conn.query(format!("alter table {} add column {} {};", table, column, define))?;
+    }
+    Ok(res == 0)
+}
+
+// turns an unsigned column signed, returns true if it was changed
+fn check_signed(conn: &mut mysql::PooledConn, table: &str, column: &str, define: &str) -> Result<bool, Error> {
+    let res = {
+        let sql = format!(r#"select count(*) from information_schema.columns where TABLE_NAME='{}' and COLUMN_NAME='{}' and COLUMN_TYPE like '%unsigned%';"#, table, column);
+        let mut qres: mysql::QueryResult = conn.query(sql.clone())?;
+        let v = qres.next()
+            .unwrap()
+            .unwrap()
+            .take(0)
+            .unwrap();
+        mysql::from_value::<i32>(v)
+    };
+    if res > 0 {
+        conn.query(format!("alter table {} modify column {} {};", table, column, define))?;
+    }
+    Ok(res > 0)
+}
+
+// close a review flag if it is still open, the flag and its status before are returned
+fn resolve_review(conn: &mut mysql::PooledConn, no: u64, confirmed: bool) -> Result<Option<(String, String, String, String, u8)>, Error> {
+    let sql = format!(r#"select a.mode as Mode, f.userid as Id, coalesce(o.userid, '') as Other, a.reason as Reason, a.status as Status
+        from rating_review as a join user as f on f.id=a.id left join user as o on o.id=a.other where a.no={};"#, no);
+    let mut flag = None;
+    for row in conn.query(sql)? {
+        let a = row?;
+        flag = Some((mysql::from_value(a.get("Mode").unwrap()), mysql::from_value(a.get("Id").unwrap()),
+            mysql::from_value(a.get("Other").unwrap()), mysql::from_value(a.get("Reason").unwrap()),
+            mysql::from_value(a.get("Status").unwrap())));
+    }
+    if let Some((_, _, _, _, 0)) = flag {
+        conn.query(format!("update rating_review set status={}, resolve_date=now() where no={};", if confirmed { 2 } else { 1 }, no))?;
+    }
+    Ok(flag)
+}
+
+// outcome of one player of a rated game
+#[derive(Clone, Debug, Default)]
+pub struct GameRating {
+    // mmr delta after the modifier and the leaver rules
+    pub value: i32,
+    pub win: bool,
+    pub rd: f32,
+    pub vol: f32,
+    pub sigma: f32,
+    pub expected: f32,
+    pub k: f32,
+    pub modifier: f32,
+}
+
+// teams in placement order, the top half of the teams counts as a win.
+// the rating math of settlement on plain rows, the recompute tool replays games with it
+pub fn rate_game(gm: &GameModeCfg, ids: &Vec<Vec<String>>, infos: &Vec<Vec<ScoreInfo>>, weight: &BTreeMap<String, f32>, modifier: &BTreeMap<String, f32>, afk: &Vec<String>)
+    -> Vec<Vec<GameRating>> {
+    let n = infos.len();
+    let scores: Vec<Vec<i32>> = infos.iter().map(|t| t.iter().map(|x| x.mmr).collect()).collect();
+    let mut devs: Vec<Vec<Glicko2Rating>> = infos.iter().map(|t| get_glicko2(t)).collect();
+    let mut sigmas: Vec<Vec<f32>> = infos.iter().map(|t| t.iter().map(|x| x.sigma).collect()).collect();
     
-    let elo = EloRank {k:20.0};
-    let (rw, rl) = elo.compute_elo_team(&win_score, &lose_score);
-    println!("{} Game Over", mode);
-    for (i, u) in win.iter().enumerate() {
-        user_score(u, (rw[i]-win_score[i]) as i16, msgtx, sender, conn, mode.clone(), true);
+    // effective K of ratings that have no K-factor, so every ledger row reads the same way
+    // a result that matched the expectation shows no K
+    let eff_k = |r: &Vec<i32>, score: &Vec<i32>, actual: f32, e: &Vec<f32>| -> Vec<f32> {
+        r.iter().enumerate().map(|(i, x)| {
+            if (actual-e[i]).abs() < 1e-4 { 0.0 } else { (x-score[i]) as f32/(actual-e[i]) }
+        }).collect()
+    };
+    let actual = |i: usize| -> f32 { (n-1-i) as f32/(n-1) as f32 };
+    let (rs, es, ks): (Vec<Vec<i32>>, Vec<Vec<f32>>, Vec<Vec<f32>>) = match gm.rating.as_str() {
+        "glicko2" => {
+            let glicko = Glicko2 {tau: gm.tau};
+            let gs = devs.clone();
+            let composite: Vec<Glicko2Rating> = gs.iter().map(|t| Glicko2::composite(t)).collect();
+            let res = glicko.compute_glicko2_placement(&gs);
+            let (mut rs, mut es, mut ks) = (vec![], vec![], vec![]);
+            for i in 0..n {
+                let e: Vec<f32> = gs[i].iter().map(|x| {
+                    composite.iter().enumerate().filter(|(t, _)| *t != i)
+                        .map(|(_, c)| glicko.get_expected(x, c)).sum::<f32>()/(n-1) as f32
+                }).collect();
+                let r: Vec<i32> = res[i].iter().map(|x| x.rating.round() as i32).collect();
+                ks.push(eff_k(&r, &scores[i], actual(i), &e));
+                rs.push(r);
+                es.push(e);
+            }
+            devs = res;
+            (rs, es, ks)
+        }
+        "trueskill" => {
+            let ts = TrueSkill {beta: gm.beta};
+            let skills: Vec<Vec<(SkillRating, f32)>> = infos.iter().zip(ids).map(|(t, id)| get_skill(t, id, weight)).collect();
+            let res = ts.rate(&skills, &(0..n).collect());
+            let (mut rs, mut es, mut ks) = (vec![], vec![], vec![]);
+            for i in 0..n {
+                sigmas[i] = res[i].iter().map(|x| x.sigma).collect();
+                let e = skills.iter().enumerate().filter(|(t, _)| *t != i)
+                    .map(|(_, b)| ts.get_expected(&skills[i], b)).sum::<f32>()/(n-1) as f32;
+                let e = vec![e; infos[i].len()];
+                let r: Vec<i32> = res[i].iter().map(|x| x.mu.round() as i32).collect();
+                ks.push(eff_k(&r, &scores[i], actual(i), &e));
+                rs.push(r);
+                es.push(e);
+            }
+            (rs, es, ks)
+        }
+        _ => {
+            let elo = EloRank {k: gm.k.k};
+            if n > 2 && gm.ffa == "battle_ground" {
+                // every team is rated on its mean, its players move at their own K
+                let ks: Vec<Vec<f32>> = infos.iter().map(|t| get_k(t, gm)).collect();
+                let (rs, e) = elo.compute_elo_battle_ground_k(&scores, n/2, gm.battle_scale, &ks);
+                let es: Vec<Vec<f32>> = scores.iter().enumerate()
+                    .map(|(i, t)| vec![e[i].to_f64().unwrap_or(0.5) as f32; t.len()])
+                    .collect();
+                (rs, es, ks)
+            } else {
+                let means: Vec<f32> = scores.iter().map(|t| mean(t)).collect();
+                let ks: Vec<Vec<f32>> = infos.iter().map(|t| get_k(t, gm)).collect();
+                let es: Vec<Vec<f32>> = scores.iter().enumerate()
+                    .map(|(i, t)| t.iter().map(|x| elo.get_expected_placement(*x as f32, &means, i)).collect())
+                    .collect();
+                let rs = elo.compute_elo_placement_k(&scores, &ks);
+                (rs, es, ks)
+            }
+        }
+    };
+    let mut res: Vec<Vec<GameRating>> = vec![];
+    for (i, team) in ids.iter().enumerate() {
+        let has_leaver = team.iter().any(|id| afk.contains(id));
+        let mut rated = vec![];
+        for (j, id) in team.iter().enumerate() {
+            let mut m = modifier.get(id).cloned().unwrap_or(1.0);
+            let mut win = i < n/2;
+            let mut penalty = 0;
+            if let Some(leaver) = &gm.leaver {
+                if afk.contains(id) {
+                    // a leaver never gains and the game counts as a loss
+                    if rs[i][j] > scores[i][j] {
+                        m = 0.0;
+                    }
+                    penalty = leaver.penalty;
+                    win = false;
+                } else if has_leaver && !win {
+                    m *= 1.0 - leaver.mitigation;
+                }
+            }
+            rated.push(GameRating {
+                value: apply_ratio(rs[i][j]-scores[i][j], m) - penalty,
+                win: win,
+                rd: devs[i][j].rd,
+                vol: devs[i][j].vol,
+                sigma: sigmas[i][j],
+                expected: es[i][j],
+                k: ks[i][j],
+                modifier: m,
+            });
+        }
+        res.push(rated);
+    }
+    res
+}
+
+fn settlement_ng_score(teams: &Vec<Vec<Rc<RefCell<User>>>>, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>, gm: &GameModeCfg, tiers: &Vec<RankTierCfg>, weight: &BTreeMap<String, f32>, game: u32, modifier: &BTreeMap<String, f32>, afk: &Vec<String>)
+    -> BTreeMap<String, ScoreRes> {
+    let mut score_res: BTreeMap<String, ScoreRes> = BTreeMap::new();
+    if teams.len() < 2 || teams.iter().any(|t| t.len() == 0) {
+        return score_res;
+    }
+    let ids: Vec<Vec<String>> = teams.iter().map(|t| t.iter().map(|u| u.borrow().id.clone()).collect()).collect();
+    let infos: Vec<Vec<ScoreInfo>> = teams.iter().map(|t| get_infos(t, gm)).collect();
+    let rated = rate_game(gm, &ids, &infos, weight, modifier, afk);
+    println!("{} Game Over", gm.mode);
+    for (i, team) in teams.iter().enumerate() {
+        for (j, u) in team.iter().enumerate() {
+            let r = &rated[i][j];
+            set_deviation(u, gm, r);
+            let ledger = SqlLedgerData {game: game, expected: r.expected, k: r.k, modifier: r.modifier, ..Default::default()};
+            match user_score(u, r.value, msgtx, sender, gm, tiers, r.win, ledger) {
+                Ok(res) => { score_res.insert(u.borrow().id.clone(), res); }
+                Err(e) => println!("user_score {:?}", e),
+            }
+        }
+    }
+    score_res
+}
+
+fn settlement_game(g: &Rc<RefCell<FightGame>>, TotalUsers: &BTreeMap<String, Rc<RefCell<User>>>, modifier: &BTreeMap<String, f32>,
+    msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>, gm: &GameModeCfg, tiers: &Vec<RankTierCfg>,
+    Leaderboards: &mut BTreeMap<String, Leaderboard>, Reviews: &mut BTreeMap<String, Review>) -> Result<(), Error> {
+    let mut teams: Vec<Vec<Rc<RefCell<User>>>> = vec![];
+    for t in &g.borrow().placement {
+        teams.push(get_users(t, TotalUsers)?);
+    }
+    let game = g.borrow().game_id;
+    let weight = g.borrow().weight.clone();
+    let afk = g.borrow().afk.clone();
+    let res = settlement_ng_score(&teams, msgtx, sender, gm, tiers, &weight, game, modifier, &afk);
+    g.borrow_mut().score_res = res;
+    g.borrow_mut().settled = true;
+    // only the leavers of a rated game lose honor and get banned
+    if let Some(leaver) = &gm.leaver {
+        for u in teams.iter().flatten() {
+            if afk.contains(&u.borrow().id) {
+                leaver_penalty(u, leaver, game, msgtx, sender)?;
+            }
+        }
+    }
+    if let Some(lb) = Leaderboards.get_mut(&gm.mode) {
+        for u in teams.iter().flatten() {
+            lb.update(&u.borrow());
+        }
+    }
+    if let Some(review) = Reviews.get_mut(&gm.mode) {
+        let now = time::get_time().sec;
+        let start = g.borrow().start_time;
+        let duration = if start > 0 { Some(now - start) } else { None };
+        for flag in review.add_game(&g.borrow().placement, duration, now) {
+            if review.cfg.freeze {
+                for id in &[&flag.id, &flag.other] {
+                    if let Some(u) = TotalUsers.get(*id) {
+                        if let Some(info) = u.borrow_mut().rank.get_mut(&gm.mode) {
+                            info.frozen = true;
+                        }
+                    }
+                }
+            }
+            sender.try_send(SqlData::AddReview(flag.clone()))?;
+            msgtx.try_send(MqttMsg{topic:format!("manager/0/res/review_flag"), 
+                msg: json!(flag).to_string(), ..Default::default()})?;
+        }
+    }
+    Ok(())
+}
+
+fn leaver_penalty(u: &Rc<RefCell<User>>, leaver: &LeaverCfg, game: u32, msgtx: &Sender<MqttMsg>, sender: &Sender<SqlData>) -> Result<(), Error> {
+    let ban = leaver.apply(&mut u.borrow_mut(), time::get_time().sec);
+    let u = u.borrow();
+    sender.try_send(SqlData::UpdateHonor(SqlHonorData {id: u.id.clone(), honor: u.honor, leaves: u.leaves, last_leave: u.last_leave}))?;
+    let res = LeaverRes {game: game, honor: u.honor, leaves: u.leaves, ban: ban};
+    msgtx.try_send(MqttMsg{topic:format!("member/{}/res/leaver", u.id), 
+        msg: json!(res).to_string(), ..Default::default()})?;
+    Ok(())
+}
+
+// hero offset of the player in the mode, a hero not chosen yet has none
+fn get_hero_offset(u: &User, mode: &String, hero: &HeroCfg) -> i32 {
+    if u.hero.len() == 0 {
+        return 0;
+    }
+    u.rank.get(mode).and_then(|info| info.heroes.get(&u.hero)).map(|h| h.mmr).unwrap_or(-hero.new)
+}
+
+// mean hero offset of the room in the mode it queues for
+fn get_hero_mmr(r: &RoomData, mode: &String, ModeCfg: &BTreeMap<String, GameModeCfg>) -> i32 {
+    match ModeCfg.get(mode).and_then(|gm| gm.hero.as_ref()) {
+        Some(hero) if r.users.len() > 0 => {
+            r.users.iter().map(|u| get_hero_offset(&u.borrow(), mode, hero)).sum::<i32>() / r.users.len() as i32
+        }
+        _ => 0,
+    }
+}
+
+// take the room out of every mode queue it waits in except keep
+fn withdraw_queues(QueueSender: &BTreeMap<String, Sender<QueueData>>, r: &mut RoomData, keep: &str) {
+    for m in &r.queue_modes {
+        if m != keep {
+            if let Some(t1) = QueueSender.get(m) {
+                t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: r.rid}));
+            }
+        }
+    }
+    r.queue_modes.retain(|m| m == keep);
+}
+
+// the hero offset of every settled player follows the result against the prestart prediction
+fn hero_rating(g: &Rc<RefCell<FightGame>>, users: &Vec<UserInfoData>, TotalUsers: &BTreeMap<String, Rc<RefCell<User>>>,
+    gm: &GameModeCfg, hero: &HeroCfg, sender: &Sender<SqlData>) -> Result<(), Error> {
+    g.borrow_mut().hero_rated = true;
+    let g = g.borrow();
+    for u in users {
+        if u.hero.len() == 0 || !g.score_res.contains_key(&u.steamid) {
+            continue;
+        }
+        let u1 = match get_user(&u.steamid, TotalUsers) {
+            Some(u1) => u1,
+            None => continue,
+        };
+        let expected = g.team_of(&u.steamid).and_then(|t| g.expected.get(t).cloned()).unwrap_or(0.5);
+        let win = g.winteam.contains(&u.steamid);
+        let mut u1 = u1.borrow_mut();
+        if let Some(info) = u1.rank.get_mut(&gm.mode) {
+            if info.frozen {
+                continue;
+            }
+            let h = info.heroes.entry(u.hero.clone()).or_insert(HeroRating {mmr: -hero.new, games: 0});
+            h.mmr = hero.update(h.mmr, expected, win);
+            h.games += 1;
+            sender.try_send(SqlData::UpdateHeroRating(SqlHeroRatingData {id: u.steamid.clone(), mode: gm.mode.clone(),
+                hero: u.hero.clone(), mmr: h.mmr, games: h.games}))?;
+        }
+    }
+    Ok(())
+}
+
+// every team ahead of another in the placement is one prediction of the prestart averages
+fn calibrate(g: &Rc<RefCell<FightGame>>, c: &mut Calibration) {
+    let g = g.borrow();
+    let order: Vec<usize> = g.placement.iter().filter_map(|t| t.get(0).and_then(|id| g.team_of(id))).collect();
+    if order.len() < 2 || order.len() != g.avg.len() {
+        return;
+    }
+    let elo = EloRank {k: 0.0};
+    for a in 0..order.len() {
+        for b in a+1..order.len() {
+            c.add(elo.get_expected(g.avg[order[a]], g.avg[order[b]]), true);
+        }
     }
-    for (i, u) in lose.iter().enumerate() {
-        user_score(u, (rl[i]-lose_score[i]) as i16, msgtx, sender, conn, mode.clone(), false);
+}
+
+// modifier of every player with stats, players without stats keep 1.0
+fn get_modifier(g: &Rc<RefCell<FightGame>>, users: &Vec<UserInfoData>, perf: &PerformanceCfg) -> BTreeMap<String, f32> {
+    let mut res: BTreeMap<String, f32> = BTreeMap::new();
+    let teams = g.borrow().placement.clone();
+    let n = teams.len();
+    for (i, team) in teams.into_iter().enumerate() {
+        let win = i < n/2;
+        let mut ids: Vec<String> = vec![];
+        let mut stats: Vec<PerformanceStats> = vec![];
+        for u in users {
+            if team.contains(&u.steamid) {
+                ids.push(u.steamid.clone());
+                stats.push(PerformanceStats {
+                    damage: u.damage as f32,
+                    be_damage: u.be_damage as f32,
+                    K: u.K as f32,
+                    D: u.D as f32,
+                    A: u.A as f32,
+                    battle_score: u.BattleScore.parse().unwrap_or(0.0),
+                });
+            }
+        }
+        for (id, m) in ids.into_iter().zip(perf.modifier(&stats, win)) {
+            res.insert(id, m);
+        }
     }
+    res
 }
 
-pub fn HandleSqlRequest(pool: mysql::Pool)
+pub fn HandleSqlRequest(msgtx: Sender<MqttMsg>, pool: mysql::Pool)
     -> Result<Sender<SqlData>, Error> {
         #[cfg(target_os = "linux")]
         let (tx1, rx1): (Sender<SqlData>, Receiver<SqlData>) = bounded(10000);
@@ -829,6 +1954,8 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
         let mut len = 0;
         let mut UpdateInfo: Vec<SqlGameInfoData> = Vec::new();
         let mut info_len = 0; 
+        let mut UpdateLedger: Vec<SqlLedgerData> = Vec::new();
+        let mut ledger_len = 0;
         let mut UpdateReplay: Vec<SqlReplayData> = Vec::new();
         let mut replay_len = 0;
         let mut ExistRP: Vec<SqlReplayData> = Vec::new();
@@ -855,11 +1982,7 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
         
         let mut ModeCfg: BTreeMap<String, GameModeCfg> = BTreeMap::new();
         for x in config.game_mode.unwrap() {
-            let gmc = GameModeCfg {
-                mode: x.MODE.clone().unwrap(),
-                team_size: x.TEAM_SIZE.unwrap(),
-                match_size: x.MATCH_SIZE.unwrap()
-            };
+            let gmc = GameModeCfg::new(&x);
             ModeCfg.insert(x.MODE.clone().unwrap(), gmc);
         }
         let modes: Vec<String> = ModeCfg.into_iter().map(|(k, v)| k).collect();
@@ -948,9 +2071,9 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
 
                         if info_len > 0 {
                             println!("SQL Update game_info");
-                            let mut insert_info: String = "insert into game_info (id, gameid, hero, damage, be_damage, K, D, A, Res, afk, BattleScore, Currency, equ, Talent_A, Talent_B, Talent_C, Talent_D, Talent_E) values".to_string();
+                            let mut insert_info: String = "insert into game_info (id, gameid, mode, hero, damage, be_damage, K, D, A, Res, afk, BattleScore, battle_score, Currency, equ, Talent_A, Talent_B, Talent_C, Talent_D, Talent_E) values".to_string();
                             for (i, info) in UpdateInfo.iter().enumerate() {
-                                let mut new_user = format!(r#" ((select id from user where userid="{}"), {}, '{}', {}, {}, {}, {}, {}, {}, {}, {}, {}, '{}', {}, {}, {}, {}, {})"#, info.id, info.game, info.hero, info.damage, info.be_damage, info.K, info.D, info.A, info.Res, info.afk, info.BattleScore, info.Currency, info.equ, info.Talent.A, info.Talent.B, info.Talent.C, info.Talent.D, info.Talent.E);
+                                let mut new_user = format!(r#" ((select id from user where userid="{}"), {}, '{}', '{}', {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, '{}', {}, {}, {}, {}, {})"#, info.id, info.game, info.mode, info.hero, info.damage, info.be_damage, info.K, info.D, info.A, info.Res, info.afk, info.BattleScore, info.battle_score, info.Currency, info.equ, info.Talent.A, info.Talent.B, info.Talent.C, info.Talent.D, info.Talent.E);
                                 insert_info += &new_user;
                                 if i < info_len-1 {
                                     insert_info += ",";
@@ -967,6 +2090,23 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
                             UpdateInfo.clear();
                         }
 
+                        if ledger_len > 0 {
+                            let mut insert_ledger: String = "insert into rating_ledger (id, gameid, mode, old_score, new_score, old_mmr, new_mmr, expected, k, modifier) values".to_string();
+                            for (i, l) in UpdateLedger.iter().enumerate() {
+                                let mut new_ledger = format!(r#" ((select id from user where userid="{}"), {}, '{}', {}, {}, {}, {}, {}, {}, {})"#, l.id, l.game, l.mode, l.old_score, l.new_score, l.old_mmr, l.new_mmr, l.expected, l.k, l.modifier);
+                                insert_ledger += &new_ledger;
+                                if i < ledger_len-1 {
+                                    insert_ledger += ",";
+                                }
+                            }
+                            insert_ledger += ";";
+                            {
+                                conn.query(insert_ledger.clone())?;
+                            }
+                            ledger_len = 0;
+                            UpdateLedger.clear();
+                        }
+
                         if replay_len > 0 {
                             let mut insert_replay: String = "insert into replay (gameid, replay, url, address) values".to_string();
                             for (i, replay) in UpdateReplay.iter().enumerate() {
@@ -1045,48 +2185,151 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
                                     }
                                     SqlData::UpdateScore(x) => {
                                         //println!("SQL Update score");
-                                        if x.mode == "ng1p2t" {
-                                            if x.Res == true {
-                                                let sql = format!("UPDATE user_ng1v1 as a JOIN user as b ON a.id=b.id SET score={}, Win=Win+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
-                                            } else {
-                                                let sql = format!("UPDATE user_ng1v1 as a JOIN user as b ON a.id=b.id SET score={}, Lose=Lose+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
+                                        // every mode has its own table, create the row on the first ranked game
+                                        let sql = format!("insert into {} (id, score, mmr, Win, Lose, rd, vol, sigma, placement, peak, season_win, season_lose, last_game, last_decay, decayed, streak, shield) values ((select id from user where userid='{}'), {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}, {}) on duplicate key update score=values(score), mmr=values(mmr), Win=values(Win), Lose=values(Lose), rd=values(rd), vol=values(vol), sigma=values(sigma), placement=values(placement), peak=values(peak), season_win=values(season_win), season_lose=values(season_lose), last_game=values(last_game), last_decay=values(last_decay), decayed=values(decayed), streak=values(streak), shield=values(shield);",
+                                            x.mode, x.id, x.info.score, x.info.mmr, x.info.WinCount, x.info.LoseCount, x.info.rd, x.info.vol, x.info.sigma, x.info.placement, x.info.peak, x.info.season_win, x.info.season_lose, x.info.last_game, x.info.last_decay, x.info.decayed, x.info.streak, x.info.shield);
+                                        let qres = conn.query(sql.clone())?;
+                                    }
+                                    SqlData::SeasonRollover(x) => {
+                                        // archive and reset in one transaction, the season row marks it done
+                                        let res = (|| -> Result<(), Error> {
+                                            let mut t = conn.start_transaction(false, None, None)?;
+                                            for mode in &x.modes {
+                                                t.query(format!("insert ignore into season_rank (season, mode, id, score, peak, Win, Lose) select {}, '{}', id, score, peak, season_win, season_lose from {};",
+                                                    x.season.id, mode, mode))?;
+                                                let frozen: Vec<String> = x.frozen.iter().filter(|(m, _)| m == mode).map(|(_, id)| format!("'{}'", id)).collect();
+                                                let skip = if frozen.len() > 0 {
+                                                    format!(" where id not in (select id from user where userid in ({}))", frozen.join(","))
+                                                } else {
+                                                    "".to_string()
+                                                };
+                                                // soft reset shows in the ledger as game 0
+                                                t.query(format!("insert into rating_ledger (id, gameid, mode, old_score, new_score, old_mmr, new_mmr, expected, k, modifier) select id, 0, '{}', score, score+round(({}-cast(score as signed))*{}), mmr, mmr+round(({}-mmr)*{}), 0, 0, 1 from {}{};",
+                                                    mode, x.season.target, x.season.ratio, x.season.target, x.season.ratio, mode, skip))?;
+                                                t.query(format!("update {} set score=score+round(({}-cast(score as signed))*{}), mmr=mmr+round(({}-mmr)*{}){};",
+                                                    mode, x.season.target, x.season.ratio, x.season.target, x.season.ratio, skip))?;
+                                                t.query(format!("update {} set peak=score, season_win=0, season_lose=0;", mode))?;
                                             }
-                                            //println!("sql: {}", sql);
-                                            
-
-                                        } else if x.mode == "ng5p2t" {
-                                            if x.Res == true {
-                                                let sql = format!("UPDATE user_ng5v5 as a JOIN user as b ON a.id=b.id SET score={}, Win=Win+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
-                                            } else {
-                                                let sql = format!("UPDATE user_ng5v5 as a JOIN user as b ON a.id=b.id SET score={}, Lose=Lose+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
+                                            t.query(format!("insert into season (id, name) values ({}, '{}');", x.season.id, x.season.name))?;
+                                            t.commit()?;
+                                            Ok(())
+                                        })();
+                                        if let Err(e) = &res {
+                                            println!("season {} rollover failed {:?}", x.season.id, e);
+                                        }
+                                        x.done.send((x.season.id, res.is_ok()))?;
+                                    }
+                                    SqlData::LeaderboardSnapshot(x) => {
+                                        let mut t = conn.start_transaction(false, None, None)?;
+                                        t.query(format!("delete from leaderboard where mode='{}';", x.mode))?;
+                                        if x.list.len() > 0 {
+                                            let mut insert_str: String = "insert into leaderboard (mode, position, id, score, Win, Lose) values".to_string();
+                                            for (i, e) in x.list.iter().enumerate() {
+                                                insert_str += &format!(r#" ('{}', {}, (select id from user where userid="{}"), {}, {}, {})"#, x.mode, e.position, e.id, e.score, e.WinCount, e.LoseCount);
+                                                if i < x.list.len()-1 {
+                                                    insert_str += ",";
+                                                }
                                             }
-                                           
-
-                                        } else if x.mode == "rk1p2t" {
-                                            if x.Res == true {
-                                                let sql = format!("UPDATE user_rk1v1 as a JOIN user as b ON a.id=b.id SET score={}, Win=Win+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
-                                            } else {
-                                                let sql = format!("UPDATE user_rk1v1 as a JOIN user as b ON a.id=b.id SET score={}, Lose=Lose+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
+                                            insert_str += ";";
+                                            t.query(insert_str)?;
+                                        }
+                                        t.commit()?;
+                                    }
+                                    SqlData::UpdateHonor(x) => {
+                                        conn.query(format!("update user_honor set honor={}, leaves={}, last_leave={} where id=(select id from user where userid='{}');",
+                                            x.honor, x.leaves, x.last_leave, x.id))?;
+                                    }
+                                    SqlData::UpdateHeroRating(x) => {
+                                        conn.query(format!("insert into hero_rating (id, mode, hero, mmr, games) values ((select id from user where userid='{}'), '{}', '{}', {}, {}) on duplicate key update mmr=values(mmr), games=values(games);",
+                                            x.id, x.mode, x.hero, x.mmr, x.games))?;
+                                    }
+                                    SqlData::SeasonStandings(x) => {
+                                        let mut res = SeasonStandingsRes {season: x.season, mode: x.mode.clone(), ..Default::default()};
+                                        let get_rank = |a: mysql::Row| -> SeasonRankRes {
+                                            SeasonRankRes {
+                                                id: mysql::from_value(a.get("userid").unwrap()),
+                                                score: mysql::from_value(a.get("Score").unwrap()),
+                                                peak: mysql::from_value(a.get("Peak").unwrap()),
+                                                WinCount: mysql::from_value(a.get("Win").unwrap()),
+                                                LoseCount: mysql::from_value(a.get("Lose").unwrap()),
                                             }
-                                            
-                                        } else if x.mode == "rk5p2t" {
-                                            if x.Res == true {
-                                                let sql = format!("UPDATE user_rk5v5 as a JOIN user as b ON a.id=b.id SET score={}, Win=Win+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
-                                            } else {
-                                                let sql = format!("UPDATE user_rk5v5 as a JOIN user as b ON a.id=b.id SET score={}, Lose=Lose+1 WHERE b.userid='{}';", x.score, x.id);
-                                                let qres = conn.query(sql.clone())?;
+                                        };
+                                        let sql = format!(r#"select userid, a.score as Score, a.peak as Peak, a.Win as Win, a.Lose as Lose from season_rank as a join user as f on a.id=f.id where a.season={} and a.mode='{}' order by a.score desc limit 100;"#, x.season, x.mode);
+                                        for row in conn.query(sql)? {
+                                            res.list.push(get_rank(row?));
+                                        }
+                                        let sql = format!(r#"select userid, a.score as Score, a.peak as Peak, a.Win as Win, a.Lose as Lose from season_rank as a join user as f on a.id=f.id where a.season={} and a.mode='{}' and f.userid='{}';"#, x.season, x.mode, x.id);
+                                        for row in conn.query(sql)? {
+                                            res.me = Some(get_rank(row?));
+                                        }
+                                        msgtx.try_send(MqttMsg{topic:format!("member/{}/res/season_standings", x.id), 
+                                            msg: json!(res).to_string(), ..Default::default()})?;
+                                    }
+                                    SqlData::RatingHistory(x) => {
+                                        let count = x.count.unwrap_or(20).min(200);
+                                        let qmodes: Vec<String> = match &x.mode {
+                                            Some(m) => vec![m.clone()],
+                                            None => modes.clone(),
+                                        };
+                                        let mut res: BTreeMap<String, Vec<RatingHistoryRes>> = BTreeMap::new();
+                                        for m in qmodes {
+                                            let sql = format!(r#"select a.gameid as Game, a.old_score as OldScore, a.new_score as NewScore, a.expected as Expected, a.k as K, a.modifier as Modifier, cast(a.create_date as char) as Date from rating_ledger as a join user as f on a.id=f.id where f.userid='{}' and a.mode='{}' order by a.no desc limit {};"#, x.id, m, count);
+                                            let qres2: mysql::QueryResult = conn.query(sql)?;
+                                            let mut list = vec![];
+                                            for row in qres2 {
+                                                let a = row?.clone();
+                                                list.push(RatingHistoryRes {
+                                                    game: mysql::from_value(a.get("Game").unwrap()),
+                                                    old_score: mysql::from_value(a.get("OldScore").unwrap()),
+                                                    new_score: mysql::from_value(a.get("NewScore").unwrap()),
+                                                    expected: mysql::from_value(a.get("Expected").unwrap()),
+                                                    k: mysql::from_value(a.get("K").unwrap()),
+                                                    modifier: mysql::from_value(a.get("Modifier").unwrap()),
+                                                    date: mysql::from_value(a.get("Date").unwrap()),
+                                                });
                                             }
-                                            
-
+                                            res.insert(m, list);
                                         }
-                                        
+                                        msgtx.try_send(MqttMsg{topic:format!("member/{}/res/rating_history", x.id), 
+                                            msg: json!(res).to_string(), ..Default::default()})?;
+                                    }
+                                    SqlData::ReviewList(x) => {
+                                        let count = x.count.unwrap_or(50).min(500);
+                                        let mut sql = format!(r#"select a.no as No, a.mode as Mode, f.userid as Id, coalesce(o.userid, '') as Other, a.reason as Reason, a.games as Games, a.wins as Wins, a.status as Status, cast(a.create_date as char) as Date
+                                            from rating_review as a join user as f on f.id=a.id left join user as o on o.id=a.other where a.status={}"#, x.status.unwrap_or(0));
+                                        if let Some(mode) = &x.mode {
+                                            sql += &format!(" and a.mode='{}'", mode);
+                                        }
+                                        sql += &format!(" order by a.no desc limit {};", count);
+                                        let qres2: mysql::QueryResult = conn.query(sql)?;
+                                        let mut list = vec![];
+                                        for row in qres2 {
+                                            let a = row?.clone();
+                                            list.push(ReviewRes {
+                                                no: mysql::from_value(a.get("No").unwrap()),
+                                                mode: mysql::from_value(a.get("Mode").unwrap()),
+                                                id: mysql::from_value(a.get("Id").unwrap()),
+                                                other: mysql::from_value(a.get("Other").unwrap()),
+                                                reason: mysql::from_value(a.get("Reason").unwrap()),
+                                                games: mysql::from_value(a.get("Games").unwrap()),
+                                                wins: mysql::from_value(a.get("Wins").unwrap()),
+                                                status: mysql::from_value(a.get("Status").unwrap()),
+                                                date: mysql::from_value(a.get("Date").unwrap()),
+                                            });
+                                        }
+                                        msgtx.try_send(MqttMsg{topic:format!("manager/0/res/review_list"), 
+                                            msg: json!(list).to_string(), ..Default::default()})?;
+                                    }
+                                    SqlData::TaskRun(x) => {
+                                        conn.query(format!("insert into task_run (name, last_run) values ('{}', {}) on duplicate key update last_run=values(last_run);", x.name, x.time))?;
+                                    }
+                                    SqlData::AddReview(x) => {
+                                        conn.query(format!("insert into rating_review (mode, id, other, reason, games, wins) values ('{}', (select id from user where userid='{}'), coalesce((select id from user where userid='{}'), 0), '{}', {}, {});",
+                                            x.mode, x.id, x.other, x.reason, x.games, x.wins))?;
+                                    }
+                                    SqlData::RatingLedger(x) => {
+                                        UpdateLedger.push(x.clone());
+                                        ledger_len += 1;
                                     }
                                     SqlData::UpdateGameInfo(x) => {
                                         //println!("SQL Update game_info");
@@ -1108,313 +2351,73 @@ pub fn HandleSqlRequest(pool: mysql::Pool)
                                         AddBlack.push(x.clone());
                                         add_black_len += 1;
                                     },
-                                    SqlData::RemoveBlack(x) => {
-                                        println!("RemoveBlack");
-                                        RemoveBlack.push(x.clone());
-                                        remove_black_len += 1;
-                                    },
-                                    SqlData::HeroNum(x) => {
-                                        hero = x.hero_type.clone();
-                                    }
-                                }
-                            }
-                            Ok(())
-                        };
-                        if let Err(msg) = handle() {
-                            println!("{:?}", msg);
-                            continue;
-                        }
-                    }
-                }
-            }
-        });
-    Ok(tx1)
-}
-
-pub fn HandleQueueRequest(msgtx: Sender<MqttMsg>, sender: Sender<RoomEventData>, mode: String, team_size: i16, match_size: usize, score_interval: i16)
-    -> Result<Sender<QueueData>, Error> {
-    #[cfg(target_os = "linux")]
-    let (tx, rx):(Sender<QueueData>, Receiver<QueueData>) = bounded(10000);
-    
-    #[cfg(not(target_os = "linux"))]
-    let (tx, rx):(Sender<QueueData>, Receiver<QueueData>) = crossbeam::unbounded();
-    let start = Instant::now();
-
-    #[cfg(target_os = "linux")]
-    let update = tick(Duration::from_millis(1000));    
-
-    #[cfg(not(target_os = "linux"))]
-    let (txxx, update) = crossbeam_channel::unbounded();
-    #[cfg(not(target_os = "linux"))]
-    std::thread::spawn(move || {
-        loop {
-            std::thread::sleep(std::time::Duration::from_millis(1000));
-            txxx.try_send(std::time::Instant::now()).unwrap();
-            //println!("update200ms: rx len: {}, tx len: {}", rx.len(), txxx.len());
-        }
-    });
-    //let mut team_size: i16 = 1;
-
-    // if mode == "ng1p2t" {
-    //     team_size = 1;
-    // } else if mode == "rk1p2t" {
-    //     team_size = 1;
-    // } else if mode == "ng5p2t" {
-    //     team_size = 5;
-    // } else if mode == "rk5p2t" {
-    //     team_size = 5;
-    // }
-
-    thread::spawn(move || -> Result<(), Error> {
-        let mut QueueRoom: BTreeMap<u32, Rc<RefCell<QueueRoomData>>> = BTreeMap::new();
-        let mut ReadyGroups: BTreeMap<u32, Rc<RefCell<ReadyGroupData>>> = BTreeMap::new();
-        
-        let mut group_id = 0;
-        loop {
-            select! {
-                recv(update) -> _ => {
-                    
-                    let mut new_now = Instant::now();
-                    
-                    if QueueRoom.len() >= match_size {
-                        let mut g: ReadyGroupData = Default::default();
-                        let mut tq: Vec<Rc<RefCell<QueueRoomData>>> = vec![];
-                        let mut id: Vec<u32> = vec![];
-                        let mut new_now = Instant::now();
-                        tq = QueueRoom.iter().map(|x|Rc::clone(x.1)).collect();
-                        let mut new_now = Instant::now();
-                        tq.sort_by_key(|x| x.borrow().avg[&mode]);
-                        //println!("Sort Time: {:?}",Instant::now().duration_since(new_now));
-                        let mut new_now1 = Instant::now();
-                        for i in 1..team_size+1 {
-                            for (k, v) in &mut QueueRoom {
-                                if v.borrow().user_len > i {
-                                    continue
-                                }
-                                let mut block: bool = false;
-                                
-                                for u in &v.borrow_mut().user_name {
-                                    if g.blacklist.contains(u) || g.block.contains(u){
-                                        block = true;
-                                        break;
-                                    }
-                                }
-                                for u in &g.user_name {
-                                    if v.borrow().blacklist.contains(&u) || v.borrow().block.contains(&u){
-                                        block = true;
-                                        break;
-                                    }
-                                }
-                                if !g.allow_same_hero {
-                                    for h in &g.hero {
-                                        if v.borrow().hero.contains(&h) {
-                                            block = true;
-                                            break;
-                                        }
-                                    }
-                                }
-                                if (g.user_len != 0 && g.honor != v.borrow().honor) {
-                                     block = true;
-                                }
-                                if block {
-                                    continue;
-                                }
-                                let mut group_score = 0;
-                                //group_score = g.avg[&mode];
-                                if let Some(avg) = g.avg.get(&mode) {
-                                    group_score = *avg;
-                                }
-                                let mut room_score = 0;
-                                //room_score = v.borrow().avg[&mode];
-                                if let Some(avg) = v.borrow().avg.get(&mode) {
-                                    room_score = *avg;
-                                }
-                                if g.user_len > 0 && g.user_len < team_size && (group_score + v.borrow().queue_cnt*score_interval) < room_score {
-                                    for r in g.rid {
-                                        id.push(r);
-                                    }
-                                    g = Default::default();
-                                    g.rid.push(v.borrow().rid);
-                                    g.max_room_len = v.borrow().user_len.clone();
-                                    g.allow_same_hero = v.borrow().allow_same_hero.clone();
-                                    g.hero = [g.hero.as_slice(), v.borrow().hero.clone().as_slice()].concat();
-                                    g.block = [g.block.as_slice(), v.borrow().block.clone().as_slice()].concat();
-                                    g.blacklist = [g.blacklist.as_slice(), v.borrow().blacklist.clone().as_slice()].concat();
-                                    g.user_name = [g.user_name.as_slice(), v.borrow().user_name.as_slice()].concat();
-                                    g.honor = v.borrow().honor.clone();
-                                    let mut ng = (group_score * g.user_len + room_score * v.borrow().user_len) as i16 / (g.user_len + v.borrow().user_len) as i16;
-                                    //g.avg[&mode] = ng;
-                                    g.avg.insert(mode.clone(), ng);
-                                    
-                                    g.user_len += v.borrow().user_len;
-                                    v.borrow_mut().ready = 1;
-                                    v.borrow_mut().gid = group_id + 1;
-                                    v.borrow_mut().queue_cnt += 1;
-                                    continue;
-                                }
-
-                                if v.borrow().ready == 0 &&
-                                    v.borrow().user_len as i16 + g.user_len <= team_size {
-                                    if g.user_len == 0 {
-                                        g.allow_same_hero = v.borrow().allow_same_hero.clone();
-                                    }
-                                    let Difference: i16 = i16::abs(room_score - group_score);
-                                    if group_score == 0 || Difference <= score_interval * v.borrow().queue_cnt {
-                                        g.rid.push(v.borrow().rid);
-                                        g.hero = [g.hero.as_slice(), v.borrow().hero.clone().as_slice()].concat();
-                                        g.block = [g.block.as_slice(), v.borrow().block.clone().as_slice()].concat();
-                                        g.user_name = [g.user_name.as_slice(), v.borrow().user_name.as_slice()].concat();
-                                        if g.user_len == 0 {
-                                            g.honor = v.borrow().honor.clone();
-                                        }
-                                        let mut score = (group_score * g.user_len + room_score * v.borrow().user_len) as i16 / (g.user_len + v.borrow().user_len) as i16;
-                                        //g.avg[&mode] = score;
-                                        g.avg.insert(mode.clone(), score);
-                                        if (g.max_room_len < v.borrow().user_len) {
-                                            g.max_room_len = v.borrow().user_len.clone()
-                                        }
-                                        g.user_len += v.borrow().user_len;
-                                        v.borrow_mut().ready = 1;
-                                        v.borrow_mut().gid = group_id + 1;
-                                    }
-                                    else {
-                                        v.borrow_mut().queue_cnt += 1;
-                                    }
-                                }
-                                if g.user_len == team_size {
-                                   
-                                    //println!("match team_size!");
-                                    group_id += 1;
-                                    //info!("new group_id: {}", group_id);
-                                    g.gid = group_id;
-                                    
-                                    g.queue_cnt = 1;
-                                    ReadyGroups.insert(group_id, Rc::new(RefCell::new(g.clone())));
-                                    
-                                    g = Default::default();
-                                }
-
-                            }
-                        }
-                        //println!("Time 2: {:?}",Instant::now().duration_since(new_now1));
-                        if g.user_len < team_size {
-                            for r in g.rid {
-                                let mut room = QueueRoom.get(&r);
-                                if let Some(room) = room {
-                                    room.borrow_mut().ready = 0;
-                                    room.borrow_mut().gid = 0;
-                                }
-                            }
-                            for r in id {
-                                let mut room = QueueRoom.get(&r);
-                                if let Some(room) = room {
-                                    room.borrow_mut().ready = 0;
-                                    room.borrow_mut().gid = 0;
-                                }
-                            }
-                        }
-                    }
-                    
-                    if ReadyGroups.len() >= match_size {
-                        let mut fg: ReadyGameData = Default::default();
-                        //let mut prestart = false;
-                        let mut total_score: i16 = 0;
-                        let mut rm_ids: Vec<u32> = vec![];
-                        let mut tq: Vec<Rc<RefCell<ReadyGroupData>>> = vec![];
-                        tq = ReadyGroups.iter().map(|x|Rc::clone(x.1)).collect();
-                        //println!("ReadyGroup!! {}", ReadyGroups.len());
-                        let mut new_now2 = Instant::now();
-                        tq.sort_by_key(|x| x.borrow().max_room_len);
-                        tq.iter().rev();
-                        
-                        for (id, rg) in &mut ReadyGroups {
-                            
-                            let mut block: bool = false;
-                            
-                            for u in &rg.borrow_mut().user_name {
-                                if fg.block.contains(u) {
-                                    block = true;
-                                    break;
-                                }                                
-                            }
-                            for u in &fg.user_name {
-                                if rg.borrow().block.contains(&u) {
-                                    block = true;
-                                    break;
-                                }   
-                            }
-                            if (fg.team_len != 0 && fg.honor != rg.borrow().honor) {
-                                block = true;
-                            }
-                            if !fg.allow_same_hero {
-                                for h in &fg.hero {
-                                    if rg.borrow().hero.contains(&h) {
-                                        block = true;
-                                        break;
-                                    }
-                                }
-                            }
-                            if block {
-                                continue;
-                            }
-                            let mut group_score = 0;
-                            //group_score = rg.borrow().avg[&mode];
-                            if let Some(avg) = rg.borrow().avg.get(&mode) {
-                                group_score = *avg;
-                            }
-                            if rg.borrow().game_status == 0 && fg.team_len < match_size {
-                                if total_score == 0 {
-                                    total_score += group_score as i16;
-                                    fg.group.push(rg.borrow().rid.clone());
-                                    fg.honor = rg.borrow().honor;
-                                    fg.gid.push(*id);
-                                    fg.team_len += 1;
-                                    fg.block = [fg.block.as_slice(), rg.borrow().block.clone().as_slice()].concat();
-                                    fg.hero = [fg.hero.as_slice(), rg.borrow().hero.clone().as_slice()].concat();
-                                    fg.allow_same_hero = rg.borrow().allow_same_hero.clone();
-                                    continue;
-                                }
-                                
-                                let mut difference = 0;
-                                if fg.team_len > 0 {
-                                    difference = i16::abs(group_score as i16 - total_score/fg.team_len as i16);
-                                }
-                                if difference <= score_interval * rg.borrow().queue_cnt {
-                                    total_score += group_score as i16;
-                                    fg.group.push(rg.borrow().rid.clone());
-                                    fg.block = [fg.block.as_slice(), rg.borrow().block.clone().as_slice()].concat();
-                                    fg.user_name = [fg.user_name.as_slice(), rg.borrow().user_name.clone().as_slice()].concat();
-                                    fg.hero = [fg.hero.as_slice(), rg.borrow().hero.clone().as_slice()].concat();
-                                    fg.team_len += 1;
-                                    fg.gid.push(*id);
-                                }
-                                else {
-                                    rg.borrow_mut().queue_cnt += 1;
-                                }
-                            }
-                            if fg.team_len == match_size {
-                                println!("{} UpdateGame", mode);
-                                //std::thread::sleep(std::time::Duration::from_millis(5000));
-                                sender.send(RoomEventData::UpdateGame(PreGameData{rid: fg.group.clone(), mode: mode.clone()}));
-                                for id in fg.gid {
-                                    rm_ids.push(id);
-                                }
-                                fg = Default::default();
-                            }
-                        }
-                        
-                        for id in rm_ids {
-                            let rg = ReadyGroups.remove(&id);
-                            if let Some(rg) = rg {
-                                for rid in &rg.borrow().rid {
-                                    
-                                    QueueRoom.remove(&rid);
-                                    
+                                    SqlData::RemoveBlack(x) => {
+                                        println!("RemoveBlack");
+                                        RemoveBlack.push(x.clone());
+                                        remove_black_len += 1;
+                                    },
+                                    SqlData::HeroNum(x) => {
+                                        hero = x.hero_type.clone();
+                                    }
                                 }
                             }
+                            Ok(())
+                        };
+                        if let Err(msg) = handle() {
+                            println!("{:?}", msg);
+                            continue;
                         }
+                    }
+                }
+            }
+        });
+    Ok(tx1)
+}
+
+pub fn HandleQueueRequest(msgtx: Sender<MqttMsg>, sender: Sender<RoomEventData>, mode: String, team_size: i16, match_size: usize, widen: WidenCfg, balance: bool, honor: HonorCfg)
+    -> Result<Sender<QueueData>, Error> {
+    #[cfg(target_os = "linux")]
+    let (tx, rx):(Sender<QueueData>, Receiver<QueueData>) = bounded(10000);
+    
+    #[cfg(not(target_os = "linux"))]
+    let (tx, rx):(Sender<QueueData>, Receiver<QueueData>) = crossbeam::unbounded();
+    let start = Instant::now();
+
+    #[cfg(target_os = "linux")]
+    let update = tick(Duration::from_millis(1000));    
+
+    #[cfg(not(target_os = "linux"))]
+    let (txxx, update) = crossbeam_channel::unbounded();
+    #[cfg(not(target_os = "linux"))]
+    std::thread::spawn(move || {
+        loop {
+            std::thread::sleep(std::time::Duration::from_millis(1000));
+            txxx.try_send(std::time::Instant::now()).unwrap();
+            //println!("update200ms: rx len: {}, tx len: {}", rx.len(), txxx.len());
+        }
+    });
+    //let mut team_size: i16 = 1;
+
+    // if mode == "ng1p2t" {
+    //     team_size = 1;
+    // } else if mode == "rk1p2t" {
+    //     team_size = 1;
+    // } else if mode == "ng5p2t" {
+    //     team_size = 5;
+    // } else if mode == "rk5p2t" {
+    //     team_size = 5;
+    // }
 
-                        //println!("Time 3: {:?}",Instant::now().duration_since(new_now2));
+    thread::spawn(move || -> Result<(), Error> {
+        let mut queue = QueueIndex::new(mode.clone(), team_size, match_size, widen, balance, honor);
+        loop {
+            select! {
+                recv(update) -> _ => {
+                    let now = time::get_time().sec;
+                    queue.match_groups(now);
+                    for game in queue.match_games(now) {
+                        println!("{} UpdateGame", mode);
+                        sender.send(RoomEventData::UpdateGame(PreGameData{rid: game, mode: mode.clone()}));
                     }
                 }
 
@@ -1422,34 +2425,16 @@ pub fn HandleQueueRequest(msgtx: Sender<MqttMsg>, sender: Sender<RoomEventData>,
                     let handle = || -> Result<(), Error> {
                         if let Ok(d) = d {
                             match d {
-                                QueueData::UpdateRoom(x) => {
+                                QueueData::UpdateRoom(mut x) => {
+                                    // the room is matched at its rating on the heroes it brings
+                                    if let Some(avg) = x.avg.get_mut(&mode) {
+                                        *avg += x.hero_mmr;
+                                    }
                                     println!("mode: {}, rid: {}, block: {:?}, honor: {}",mode, x.rid, x.block, x.honor);
-                                    QueueRoom.insert(x.rid.clone(), Rc::new(RefCell::new(x.clone())));
+                                    queue.insert_room(x);
                                 }
                                 QueueData::RemoveRoom(x) => {
-                                    //println!("Remove Room!!! mode, {}, x.rid: {}",mode, &x.rid);
-                                    let r = QueueRoom.get(&x.rid);
-                                    if let Some(r) = r {
-                                        //println!("Start Remove!! gid: {}", &r.borrow().gid);
-                                        let mut rg = ReadyGroups.get(&r.borrow().gid);
-                                        if let Some(rg) = rg {
-                                            //println!("ReadyGroup Remove!!");
-                                            for rid in &rg.borrow().rid {
-                                                if rid == &x.rid {
-                                                    continue;
-                                                }
-                                                let mut room = QueueRoom.get(rid);
-                                                if let Some(room) = room {
-                                                    
-                                                    room.borrow_mut().gid = 0;
-                                                    room.borrow_mut().ready = 0;
-                                                    //println!("gid: {}, rid: {}, ready: {}", room.borrow().gid, room.borrow().rid, room.borrow().ready);
-                                                }
-                                            }
-                                        }
-                                        ReadyGroups.remove(&r.borrow().gid);
-                                    }
-                                    QueueRoom.remove(&x.rid);
+                                    queue.remove_room(x.rid);
                                 }
                             }
                         }
@@ -1493,20 +2478,35 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
     
     let score_interval = config.game_setting.clone().unwrap().SCORE_INTERVAL.unwrap();
     let honor_threshold = config.game_setting.clone().unwrap().HONOR_THRESHOLD.unwrap();
+    // without tiers the threshold splits the queue in two as before
+    let honor_cfg = HonorCfg {
+        tiers: config.game_setting.clone().unwrap().HONOR_TIERS.unwrap_or(vec![honor_threshold]),
+        delay: config.game_setting.clone().unwrap().LOW_PRIORITY_DELAY.unwrap_or(0),
+        merge_wait: config.game_setting.clone().unwrap().HONOR_MERGE_WAIT.unwrap_or(i64::max_value()),
+    };
     let block_recent_player_of_games = config.game_setting.clone().unwrap().BLOCK_RECENT_PLAYER_OF_GAMES.unwrap();
     
     for x in config.game_mode.unwrap() {
-        let gmc = GameModeCfg {
-            mode: x.MODE.clone().unwrap(),
-            team_size: x.TEAM_SIZE.unwrap(),
-            match_size: x.MATCH_SIZE.unwrap()
+        let gmc = GameModeCfg::new(&x);
+        // without a curve the window starts at the score interval and grows by it every second
+        let initial = x.MATCH_WINDOW.or(x.SCORE_INTERVAL).unwrap_or(score_interval);
+        let widen = WidenCfg {
+            initial: initial,
+            growth: x.MATCH_WINDOW_GROWTH.unwrap_or(initial as f32),
+            cap: x.MATCH_WINDOW_CAP.unwrap_or(i32::max_value()),
         };
-        let mut tx1 = HandleQueueRequest(msgtx.clone(), tx.clone(), x.MODE.clone().unwrap(), x.TEAM_SIZE.unwrap(), x.MATCH_SIZE.unwrap(), score_interval)?;
+        let mut tx1 = HandleQueueRequest(msgtx.clone(), tx.clone(), x.MODE.clone().unwrap(), x.TEAM_SIZE.unwrap(), x.MATCH_SIZE.unwrap(), widen, x.BALANCE_TEAMS.unwrap_or(false), honor_cfg.clone())?;
         QueueSender.insert(x.MODE.clone().unwrap(), tx1.clone());
         ModeCfg.insert(x.MODE.clone().unwrap(), gmc);
     }
     let modes: Vec<String> = ModeCfg.clone().into_iter().map(|(k, v)| k).collect();
     let hero = config.game_setting.clone().unwrap().HERO.unwrap();
+    let RankTiers = get_tier_cfg(config.rank_tier.clone().unwrap_or_default());
+    let Seasons = get_season_cfg(config.season.clone().unwrap_or_default());
+    let leaderboard_size = config.game_setting.clone().unwrap().LEADERBOARD_SIZE.unwrap_or(100);
+    let leaderboard_snapshot_interval = config.game_setting.clone().unwrap().LEADERBOARD_SNAPSHOT_INTERVAL.unwrap_or(300);
+    let calibration_report_interval = config.game_setting.clone().unwrap().CALIBRATION_REPORT_INTERVAL.unwrap_or(3600);
+    let settle_wait = config.game_setting.clone().unwrap().SETTLE_WAIT.unwrap_or(120);
     sender.try_send(SqlData::HeroNum(SqlHeroname {hero_type: hero.clone()}));    
     
     #[cfg(target_os = "linux")]
@@ -1546,6 +2546,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
             txxx.try_send(std::time::Instant::now()).unwrap();
         }
     });
+
     
     let start = Instant::now();
     //let QueueSender = tx1.clone();
@@ -1587,17 +2588,153 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
             if res == 0 {
                 let insert_sql = format!(r#"create TABLE {} (
                     id INT UNSIGNED NOT NULL,
-                    score INT UNSIGNED NOT NULL,
+                    score INT NOT NULL,
+                    mmr INT NOT NULL DEFAULT 1000,
                     Win INT UNSIGNED NOT NULL,
                     Lose INT UNSIGNED NOT NULL,
+                    rd FLOAT NOT NULL DEFAULT {},
+                    vol FLOAT NOT NULL DEFAULT {},
+                    sigma FLOAT NOT NULL DEFAULT {},
+                    placement INT UNSIGNED NOT NULL DEFAULT 0,
+                    peak INT NOT NULL DEFAULT 1000,
+                    season_win INT UNSIGNED NOT NULL DEFAULT 0,
+                    season_lose INT UNSIGNED NOT NULL DEFAULT 0,
+                    last_game BIGINT NOT NULL DEFAULT 0,
+                    last_decay BIGINT NOT NULL DEFAULT 0,
+                    decayed INT NOT NULL DEFAULT 0,
+                    streak INT NOT NULL DEFAULT 0,
+                    shield INT UNSIGNED NOT NULL DEFAULT 0,
                     create_date DATETIME DEFAULT CURRENT_TIMESTAMP,
                     PRIMARY KEY (id)
-                );"#, mode);
+                );"#, mode, GLICKO2_DEFAULT_RD, GLICKO2_DEFAULT_VOL, TRUESKILL_DEFAULT_SIGMA);
                 let mut conn = pool.get_conn()?;
                 conn.query(insert_sql.clone())?;
             }
+            let mut conn = pool.get_conn()?;
+            check_column(&mut conn, &mode, "rd", &format!("FLOAT NOT NULL DEFAULT {}", GLICKO2_DEFAULT_RD))?;
+            check_column(&mut conn, &mode, "vol", &format!("FLOAT NOT NULL DEFAULT {}", GLICKO2_DEFAULT_VOL))?;
+            check_column(&mut conn, &mode, "placement", "INT UNSIGNED NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "peak", "INT NOT NULL DEFAULT 1000")?;
+            // scores go below 0 through leaver penalties and decay
+            check_signed(&mut conn, &mode, "score", "INT NOT NULL")?;
+            check_signed(&mut conn, &mode, "peak", "INT NOT NULL DEFAULT 1000")?;
+            check_column(&mut conn, &mode, "season_win", "INT UNSIGNED NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "season_lose", "INT UNSIGNED NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "last_game", "BIGINT NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "last_decay", "BIGINT NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "decayed", "INT NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "streak", "INT NOT NULL DEFAULT 0")?;
+            check_column(&mut conn, &mode, "shield", "INT UNSIGNED NOT NULL DEFAULT 0")?;
+            if check_column(&mut conn, &mode, "sigma", &format!("FLOAT NOT NULL DEFAULT {}", TRUESKILL_DEFAULT_SIGMA))? {
+                // trueskill modes kept the sigma in rd before
+                if ModeCfg.get(&mode).map(|gm| gm.rating == "trueskill").unwrap_or(false) {
+                    conn.query(format!("update {} set sigma=rd, rd={};", mode, GLICKO2_DEFAULT_RD))?;
+                }
+            }
+            if check_column(&mut conn, &mode, "mmr", "INT NOT NULL DEFAULT 1000")? {
+                // existing players start with the mmr at their visible score
+                conn.query(format!("update {} set mmr=score;", mode))?;
+            }
         }
-        let sql = format!(r#"select userid, name, Level, Exp, Money, Currency, e.honor as honor from user as f join user_honor as e on e.id=f.id;"#);
+        // lets the recompute tool replay game_info per mode
+        check_column(&mut conn, "game_info", "mode", "VARCHAR(32) NOT NULL DEFAULT ''")?;
+        check_column(&mut conn, "game_info", "battle_score", "FLOAT NOT NULL DEFAULT 0")?;
+        check_column(&mut conn, "user_honor", "leaves", "INT UNSIGNED NOT NULL DEFAULT 0")?;
+        check_column(&mut conn, "user_honor", "last_leave", "BIGINT NOT NULL DEFAULT 0")?;
+        check_table(&mut conn, "season", r#"create TABLE season (
+            id INT UNSIGNED NOT NULL,
+            name VARCHAR(64) NOT NULL,
+            create_date DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (id)
+        );"#)?;
+        check_table(&mut conn, "season_rank", r#"create TABLE season_rank (
+            season INT UNSIGNED NOT NULL,
+            mode VARCHAR(32) NOT NULL,
+            id INT UNSIGNED NOT NULL,
+            score INT NOT NULL,
+            peak INT NOT NULL,
+            Win INT UNSIGNED NOT NULL,
+            Lose INT UNSIGNED NOT NULL,
+            PRIMARY KEY (season, mode, id)
+        );"#)?;
+        // append only, one row per rating change
+        check_table(&mut conn, "rating_ledger", r#"create TABLE rating_ledger (
+            no BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+            id INT UNSIGNED NOT NULL,
+            gameid INT UNSIGNED NOT NULL,
+            mode VARCHAR(32) NOT NULL,
+            old_score INT NOT NULL,
+            new_score INT NOT NULL,
+            old_mmr INT NOT NULL DEFAULT 0,
+            new_mmr INT NOT NULL DEFAULT 0,
+            expected FLOAT NOT NULL,
+            k FLOAT NOT NULL,
+            modifier FLOAT NOT NULL DEFAULT 1,
+            create_date DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (no),
+            INDEX (id, mode)
+        );"#)?;
+        check_signed(&mut conn, "season_rank", "score", "INT NOT NULL")?;
+        check_signed(&mut conn, "season_rank", "peak", "INT NOT NULL")?;
+        check_column(&mut conn, "rating_ledger", "modifier", "FLOAT NOT NULL DEFAULT 1")?;
+        check_column(&mut conn, "rating_ledger", "old_mmr", "INT NOT NULL DEFAULT 0")?;
+        check_column(&mut conn, "rating_ledger", "new_mmr", "INT NOT NULL DEFAULT 0")?;
+        // snapshot of the top players for the website
+        check_table(&mut conn, "leaderboard", r#"create TABLE leaderboard (
+            mode VARCHAR(32) NOT NULL,
+            position INT UNSIGNED NOT NULL,
+            id INT UNSIGNED NOT NULL,
+            score INT NOT NULL,
+            Win INT UNSIGNED NOT NULL,
+            Lose INT UNSIGNED NOT NULL,
+            update_date DATETIME DEFAULT CURRENT_TIMESTAMP,
+            PRIMARY KEY (mode, position)
+        );"#)?;
+        // flags of the boosting detector, other is 0 when the flag has no opponent
+        check_table(&mut conn, "rating_review", r#"create TABLE rating_review (
+            no BIGINT UNSIGNED NOT NULL AUTO_INCREMENT,
+            mode VARCHAR(32) NOT NULL,
+            id INT UNSIGNED NOT NULL,
+            other INT UNSIGNED NOT NULL DEFAULT 0,
+            reason VARCHAR(32) NOT NULL,
+            games INT UNSIGNED NOT NULL,
+            wins INT UNSIGNED NOT NULL,
+            status TINYINT UNSIGNED NOT NULL DEFAULT 0,
+            create_date DATETIME DEFAULT CURRENT_TIMESTAMP,
+            resolve_date DATETIME NULL,
+            PRIMARY KEY (no),
+            KEY (status, mode)
+        );"#)?;
+        check_table(&mut conn, "hero_rating", r#"create TABLE hero_rating (
+            id INT UNSIGNED NOT NULL,
+            mode VARCHAR(32) NOT NULL,
+            hero VARCHAR(64) NOT NULL,
+            mmr INT NOT NULL DEFAULT 0,
+            games INT UNSIGNED NOT NULL DEFAULT 0,
+            PRIMARY KEY (id, mode, hero)
+        );"#)?;
+        check_table(&mut conn, "task_run", r#"create TABLE task_run (
+            name VARCHAR(32) NOT NULL,
+            last_run BIGINT NOT NULL DEFAULT 0,
+            PRIMARY KEY (name)
+        );"#)?;
+        let mut last_decay_run: i64 = 0;
+        let mut decay_started = false;
+        let qres2: mysql::QueryResult = conn.query("select last_run from task_run where name='decay';")?;
+        for row in qres2 {
+            let a = row?.clone();
+            last_decay_run = mysql::from_value(a.get("last_run").unwrap());
+        }
+        let mut ArchivedSeasons: Vec<u32> = vec![];
+        let (season_tx, season_rx): (Sender<(u32, bool)>, Receiver<(u32, bool)>) = bounded(10);
+        // send time of the rollover the SQL thread has not reported yet
+        let mut rollover_pending: Option<(i64, Vec<(String, String)>)> = None;
+        let qres2: mysql::QueryResult = conn.query("select id from season;")?;
+        for row in qres2 {
+            let a = row?.clone();
+            ArchivedSeasons.push(mysql::from_value(a.get("id").unwrap()));
+        }
+        let sql = format!(r#"select userid, name, Level, Exp, Money, Currency, e.honor as honor, e.leaves as leaves, e.last_leave as last_leave from user as f join user_honor as e on e.id=f.id;"#);
         let qres2: mysql::QueryResult = conn.query(sql.clone())?;
         let mut userid: String = "".to_owned();
         let mut name: String = "".to_owned();
@@ -1611,6 +2748,8 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                 hero: mysql::from_value(a.get("name").unwrap()),
                 online: false,
                 honor: mysql::from_value(a.get("honor").unwrap()),
+                leaves: mysql::from_value(a.get("leaves").unwrap()),
+                last_leave: mysql::from_value(a.get("last_leave").unwrap()),
                 ..Default::default()
             };
             user.info.PlayerLv =  mysql::from_value(a.get("Level").unwrap());
@@ -1619,20 +2758,108 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
             TotalUsers.insert(id, Rc::new(RefCell::new(user.clone())));
         }
         for mode in modes.clone() {
-            let sql = format!(r#"select userid, a.score as Score, a.Win as Win, a.Lose as Lose from user as f join {} as a on a.id=f.id;"#, mode);
+            let sql = format!(r#"select userid, a.score as Score, a.mmr as Mmr, a.Win as Win, a.Lose as Lose, a.rd as RD, a.vol as Vol, a.sigma as Sigma, a.placement as Placement, a.peak as Peak, a.season_win as SeasonWin, a.season_lose as SeasonLose, a.last_game as LastGame, a.last_decay as LastDecay, a.decayed as Decayed, a.streak as Streak, a.shield as Shield from user as f join {} as a on a.id=f.id;"#, mode);
             let qres2: mysql::QueryResult = conn.query(sql.clone())?;
             for row in qres2 {
                 let a = row?.clone();
                 let id: String =mysql::from_value(a.get("userid").unwrap());
                 let info = ScoreInfo {
                     score: mysql::from_value(a.get("Score").unwrap()),
+                    mmr: mysql::from_value(a.get("Mmr").unwrap()),
                     WinCount: mysql::from_value(a.get("Win").unwrap()),
                     LoseCount: mysql::from_value(a.get("Lose").unwrap()),
+                    rd: mysql::from_value(a.get("RD").unwrap()),
+                    vol: mysql::from_value(a.get("Vol").unwrap()),
+                    sigma: mysql::from_value(a.get("Sigma").unwrap()),
+                    placement: mysql::from_value(a.get("Placement").unwrap()),
+                    peak: mysql::from_value(a.get("Peak").unwrap()),
+                    season_win: mysql::from_value(a.get("SeasonWin").unwrap()),
+                    season_lose: mysql::from_value(a.get("SeasonLose").unwrap()),
+                    last_game: mysql::from_value(a.get("LastGame").unwrap()),
+                    last_decay: mysql::from_value(a.get("LastDecay").unwrap()),
+                    decayed: mysql::from_value(a.get("Decayed").unwrap()),
+                    streak: mysql::from_value(a.get("Streak").unwrap()),
+                    shield: mysql::from_value(a.get("Shield").unwrap()),
+                    frozen: false,
+                    heroes: BTreeMap::new(),
                 };
                 let u = TotalUsers.get_mut(&id).unwrap();
                 u.borrow_mut().rank.insert(mode.clone(), info);
             }
         }
+        let qres2: mysql::QueryResult = conn.query("select f.userid as Id, a.mode as Mode, a.hero as Hero, a.mmr as Mmr, a.games as Games from hero_rating as a join user as f on f.id=a.id;")?;
+        for row in qres2 {
+            let a = row?.clone();
+            let id: String = mysql::from_value(a.get("Id").unwrap());
+            let mode: String = mysql::from_value(a.get("Mode").unwrap());
+            if let Some(u) = TotalUsers.get(&id) {
+                if let Some(info) = u.borrow_mut().rank.get_mut(&mode) {
+                    info.heroes.insert(mysql::from_value(a.get("Hero").unwrap()), HeroRating {
+                        mmr: mysql::from_value(a.get("Mmr").unwrap()),
+                        games: mysql::from_value(a.get("Games").unwrap()),
+                    });
+                }
+            }
+        }
+        for u in TotalUsers.values() {
+            u.borrow_mut().update_hidden_rank();
+        }
+        let mut Leaderboards: BTreeMap<String, Leaderboard> = BTreeMap::new();
+        for mode in modes.clone() {
+            let mut lb = Leaderboard::new(mode.clone());
+            lb.rebuild(&TotalUsers);
+            Leaderboards.insert(mode.clone(), lb);
+        }
+        let mut last_snapshot = Instant::now();
+        let mut Reviews: BTreeMap<String, Review> = BTreeMap::new();
+        for (mode, gm) in &ModeCfg {
+            if let Some(cfg) = &gm.review {
+                let mut review = Review::new(mode.clone(), cfg.clone());
+                // settled games still inside the window, winners first
+                let sql = format!(r#"select g.gameid as Game, f.userid as Id, g.Res as Res, cast(unix_timestamp(l.create_date) as signed) as T from game_info as g
+                    join user as f on f.id=g.id join rating_ledger as l on l.gameid=g.gameid and l.id=g.id
+                    where l.mode='{}' and l.create_date > now() - interval {} second order by g.gameid;"#, mode, cfg.window);
+                let qres2: mysql::QueryResult = conn.query(sql.clone())?;
+                let mut games: Vec<(u32, i64, Vec<Vec<String>>)> = vec![];
+                for row in qres2 {
+                    let a = row?.clone();
+                    let game: u32 = mysql::from_value(a.get("Game").unwrap());
+                    let win: bool = mysql::from_value(a.get("Res").unwrap());
+                    if games.last().map(|x| x.0) != Some(game) {
+                        games.push((game, mysql::from_value(a.get("T").unwrap()), vec![vec![], vec![]]));
+                    }
+                    games.last_mut().unwrap().2[if win { 0 } else { 1 }].push(mysql::from_value(a.get("Id").unwrap()));
+                }
+                for (_, t, placement) in games {
+                    review.replay(&placement, t);
+                }
+                Reviews.insert(mode.clone(), review);
+            }
+        }
+        let sql = format!(r#"select a.mode as Mode, f.userid as Id, coalesce(o.userid, '') as Other, a.reason as Reason from rating_review as a
+            join user as f on f.id=a.id left join user as o on o.id=a.other where a.status=0;"#);
+        let qres2: mysql::QueryResult = conn.query(sql.clone())?;
+        for row in qres2 {
+            let a = row?.clone();
+            let mode: String = mysql::from_value(a.get("Mode").unwrap());
+            let id: String = mysql::from_value(a.get("Id").unwrap());
+            let other: String = mysql::from_value(a.get("Other").unwrap());
+            let reason: String = mysql::from_value(a.get("Reason").unwrap());
+            if let Some(review) = Reviews.get_mut(&mode) {
+                review.open(&id, &other, &reason);
+                if review.cfg.freeze {
+                    for id in &[&id, &other] {
+                        if let Some(u) = TotalUsers.get(*id) {
+                            if let Some(info) = u.borrow_mut().rank.get_mut(&mode) {
+                                info.frozen = true;
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        let mut Calibrations: BTreeMap<String, Calibration> = BTreeMap::new();
+        let mut last_calibration = Instant::now();
         let es = format!(r#"select b.userid, a.equ_id, a.Rank, a.Lv, a.Lv5, a.Option1, a.Option2, a.Option3, a.Option1Lv, a.Option2Lv, a.Option3Lv from equ_info as a join user as b on a.id=b.id;"#);
         let eq = conn.query(es.clone())?;
             
@@ -1964,14 +3191,17 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                 }
                                 for t in &group.borrow().teams {
                                     for r in &t.borrow().rooms {
-                                        if !rm_rid.contains(&r.borrow().rid) {
-                                            let mut h: bool;
-                                            
-                                            if r.borrow().avg_honor.clone() < honor_threshold {
-                                                h = false;
-                                            } else {
-                                                h = true;
-                                            }
+                                        if rm_rid.contains(&r.borrow().rid) {
+                                            continue;
+                                        }
+                                        // back into every queue the room started in, the match narrowed it to one
+                                        let mut start_modes = r.borrow().start_modes.clone();
+                                        if start_modes.len() == 0 {
+                                            start_modes.push(group.borrow().mode.clone());
+                                        }
+                                        r.borrow_mut().queue_modes = start_modes.clone();
+                                        for m in &start_modes {
+                                            let h = honor_cfg.tier(r.borrow().avg_honor);
                                             let mut data = QueueRoomData {
                                                 user_name: users.clone(),
                                                 hero: heros.clone(),
@@ -1981,16 +3211,18 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 avg: r.borrow().avg.clone(),
                                                 honor: h,
                                                 allow_same_hero: true,
-                                                mode: group.borrow().mode.clone(),
+                                                mode: m.clone(),
                                                 ready: 0,
                                                 queue_cnt: 1,
                                                 block: block.clone(),
                                                 blacklist: blacklist.clone(),
+                                                hero_mmr: get_hero_mmr(&r.borrow(), m, &ModeCfg),
+                                                queue_time: r.borrow().queue_time,
                                             };
                                             if data.mode == "rk1p2t" || data.mode == "rk5p2t" {
                                                 data.allow_same_hero = true;
                                             }
-                                            let t1 = QueueSender.get(&group.borrow().mode.clone());
+                                            let t1 = QueueSender.get(m);
                                             if let Some(t1) = t1{
                                                 t1.send(QueueData::UpdateRoom(data));
                                             }
@@ -2048,6 +3280,128 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                 }
                 
                 recv(update20000ms) -> _ => {
+                    let now = time::get_time().sec;
+                    // inactivity decay, on the first tick after startup and a day after the last run
+                    if !decay_started || now - last_decay_run >= 86400 {
+                        decay_started = true;
+                        last_decay_run = now;
+                        sender.try_send(SqlData::TaskRun(SqlTaskData {name: "decay".to_string(), time: now}))?;
+                        for (mode, review) in &mut Reviews {
+                            review.prune(now);
+                        }
+                        for (id, u) in &TotalUsers {
+                            let mut decayed: Vec<(String, ScoreInfo, i32)> = vec![];
+                            for (mode, info) in u.borrow_mut().rank.iter_mut() {
+                                if info.WinCount + info.LoseCount == 0 {
+                                    continue;
+                                }
+                                if let Some(decay) = ModeCfg.get(mode).and_then(|gm| gm.decay.clone()) {
+                                    let old = info.score;
+                                    let lost = decay.apply(info, now);
+                                    if lost > 0 || info.last_decay == now {
+                                        decayed.push((mode.clone(), info.clone(), old));
+                                    }
+                                }
+                            }
+                            for (mode, info, old) in decayed {
+                                sender.send(SqlData::UpdateScore(SqlScoreData {id: id.clone(), mode: mode.clone(), info: info.clone()}))?;
+                                if old != info.score {
+                                    sender.send(SqlData::RatingLedger(SqlLedgerData {id: id.clone(), game: 0, mode: mode.clone(),
+                                        old_score: old, new_score: info.score, old_mmr: info.mmr, new_mmr: info.mmr, expected: 0.0, k: 0.0, modifier: 1.0}))?;
+                                    if let Some(lb) = Leaderboards.get_mut(&mode) {
+                                        lb.update(&u.borrow());
+                                    }
+                                }
+                            }
+                            if u.borrow().online {
+                                decay_notice(u, &msgtx, &sender)?;
+                            }
+                        }
+                    }
+                    // season rollover, the SQL thread archives the standings and resets them in one transaction,
+                    // memory follows once it reports the commit and a failed rollover is sent again
+                    while let Ok((id, ok)) = season_rx.try_recv() {
+                        let (sent, frozen) = rollover_pending.take().unwrap_or((now, vec![]));
+                        if !ok {
+                            continue;
+                        }
+                        if let Some(season) = Seasons.iter().find(|x| x.id == id) {
+                            for (id, u) in &TotalUsers {
+                                for (mode, info) in u.borrow_mut().rank.iter_mut() {
+                                    // the rating of a player under review stays as it is
+                                    if !frozen.contains(&(mode.clone(), id.clone())) {
+                                        info.score = season.soft_reset(info.score);
+                                        info.mmr = season.soft_reset(info.mmr);
+                                    }
+                                    info.peak = info.score;
+                                    info.season_win = 0;
+                                    info.season_lose = 0;
+                                    // a game settled while the rollover was pending wrote the score over the reset
+                                    if info.last_game >= sent {
+                                        if let Err(e) = sender.try_send(SqlData::UpdateScore(SqlScoreData {id: id.clone(), mode: mode.clone(), info: info.clone()})) {
+                                            println!("season {} score {} {:?}", season.id, id, e);
+                                        }
+                                    }
+                                }
+                            }
+                            ArchivedSeasons.push(season.id);
+                            for (mode, lb) in &mut Leaderboards {
+                                lb.rebuild(&TotalUsers);
+                            }
+                        }
+                    }
+                    // only the latest ended season resets, so a fresh database skips the older ones instead of resetting once per season
+                    if let Some(season) = Seasons.iter().filter(|x| x.end <= now).last() {
+                        if !ArchivedSeasons.contains(&season.id) && rollover_pending.is_none() {
+                            println!("season {} rollover, current season {:?}", season.id, current_season(&Seasons, now).map(|x| x.id));
+                            let mut frozen: Vec<(String, String)> = vec![];
+                            for (id, u) in &TotalUsers {
+                                for (mode, info) in &u.borrow().rank {
+                                    if info.frozen {
+                                        frozen.push((mode.clone(), id.clone()));
+                                    }
+                                }
+                            }
+                            match sender.try_send(SqlData::SeasonRollover(SqlSeasonData {season: season.clone(), modes: modes.clone(), frozen: frozen.clone(), done: season_tx.clone()})) {
+                                Ok(_) => rollover_pending = Some((now, frozen)),
+                                Err(e) => println!("season {} rollover {:?}", season.id, e),
+                            }
+                        }
+                    }
+                    if last_snapshot.elapsed() >= Duration::from_secs(leaderboard_snapshot_interval) {
+                        last_snapshot = Instant::now();
+                        for (mode, lb) in &Leaderboards {
+                            sender.try_send(SqlData::LeaderboardSnapshot(SqlLeaderboardData {mode: mode.clone(), list: lb.top(leaderboard_size)}))?;
+                        }
+                    }
+                    if last_calibration.elapsed() >= Duration::from_secs(calibration_report_interval) {
+                        last_calibration = Instant::now();
+                        for (mode, c) in &Calibrations {
+                            msgtx.try_send(MqttMsg{topic:format!("manager/0/res/calibration"), 
+                                msg: json!(CalibrationRes::new(mode.clone(), c)).to_string(), ..Default::default()})?;
+                        }
+                    }
+                    // a game missing game info settles without modifiers, one missing game over ends unrated
+                    let mut late: Vec<GameInfoData> = vec![];
+                    for (id, g) in &GameingGroups {
+                        let (over_time, info_time, settled) = (g.borrow().over_time, g.borrow().info_time, g.borrow().settled);
+                        if over_time > 0 && now - over_time >= settle_wait && !settled {
+                            let mode = g.borrow().mode.clone();
+                            if let Some(gm) = ModeCfg.get(&mode) {
+                                println!("game {} settles without game info", id);
+                                settlement_game(g, &TotalUsers, &BTreeMap::new(), &msgtx, &sender, gm, &RankTiers, &mut Leaderboards, &mut Reviews)?;
+                            }
+                        }
+                        if info_time > 0 && now - info_time >= settle_wait {
+                            if let Some(users) = g.borrow_mut().info.take() {
+                                late.push(GameInfoData {game: *id, users: users});
+                            }
+                        }
+                    }
+                    for info in late {
+                        println!("game {} ends without game over", info.game);
+                        tx2.try_send(RoomEventData::GameInfo(info))?;
+                    }
                     for (id, group) in &mut GameingGroups {
                         if group.borrow().game_start == false {
                             if group.borrow().server_notify > 5 {
@@ -2168,13 +3522,47 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                     }
                                 },
                                 RoomEventData::GameOver(x) => {
-                                    let win = get_users(&x.win, &TotalUsers)?;
-                                    let lose = get_users(&x.lose, &TotalUsers)?;
                                     let g1 = GameingGroups.get(&x.game);
                                     if let Some(g1) = g1 {
-                                        g1.borrow_mut().winteam = x.win;
-                                        g1.borrow_mut().loseteam = x.lose;
-                                        settlement_ng_score(&win, &lose, &msgtx, &sender, &mut conn, g1.borrow().mode.clone());
+                                        // a repeated game over never rates the game twice
+                                        if g1.borrow().settled {
+                                            return Ok(());
+                                        }
+                                        // a placement ordering replaces win and lose, its top half wins
+                                        let placement = match x.rank {
+                                            Some(rank) if rank.len() > 0 => {
+                                                if !g1.borrow().check_rank(&rank) {
+                                                    println!("game {} rank does not match its teams", x.game);
+                                                    return Ok(());
+                                                }
+                                                rank
+                                            }
+                                            _ => vec![x.win, x.lose],
+                                        };
+                                        let first = g1.borrow().placement.len() == 0;
+                                        if first {
+                                            g1.borrow_mut().over_time = time::get_time().sec;
+                                        }
+                                        let half = placement.len()/2;
+                                        g1.borrow_mut().winteam = placement[..half].concat();
+                                        g1.borrow_mut().loseteam = placement[half..].concat();
+                                        g1.borrow_mut().placement = placement;
+                                        g1.borrow_mut().weight = x.weight.unwrap_or_default();
+                                        let mode = g1.borrow().mode.clone();
+                                        if first {
+                                            calibrate(g1, Calibrations.entry(mode.clone()).or_default());
+                                        }
+                                        if let Some(gm) = ModeCfg.get(&mode) {
+                                            // with a performance modifier or leaver penalty settlement waits for game info
+                                            if !gm.settle_on_info() {
+                                                settlement_game(g1, &TotalUsers, &BTreeMap::new(), &msgtx, &sender, gm, &RankTiers, &mut Leaderboards, &mut Reviews)?;
+                                            }
+                                        }
+                                        // game info that came first is handled now that the placement is known
+                                        let info = g1.borrow_mut().info.take();
+                                        if let Some(users) = info {
+                                            tx2.try_send(RoomEventData::GameInfo(GameInfoData {game: x.game, users: users}))?;
+                                        }
                                     }
                                 },
                                 RoomEventData::GameInfo(x) => {
@@ -2182,10 +3570,36 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                     let mut data1: GameInfoRes = Default::default();
                                     data1.game = x.game.clone();
                                     let g = GameingGroups.get(&x.game);
+                                    // game info ahead of game over waits on the game, settlement needs both
+                                    if let Some(g) = g {
+                                        if g.borrow().placement.len() == 0 && g.borrow().info_time == 0 {
+                                            g.borrow_mut().info = Some(x.users.clone());
+                                            g.borrow_mut().info_time = time::get_time().sec;
+                                            return Ok(());
+                                        }
+                                    }
                                     let mut users: Vec<String> = Vec::new();
                                     match g {
                                         Some(g) => {
                                             println!("Game Info");
+                                            let mode = g.borrow().mode.clone();
+                                            if let Some(gm) = ModeCfg.get(&mode) {
+                                                if gm.settle_on_info() && !g.borrow().settled && g.borrow().winteam.len() > 0 {
+                                                    let modifier = match &gm.perf {
+                                                        Some(perf) => get_modifier(g, &x.users, perf),
+                                                        None => BTreeMap::new(),
+                                                    };
+                                                    if gm.leaver.is_some() {
+                                                        g.borrow_mut().afk = x.users.iter().filter(|u| u.afk).map(|u| u.steamid.clone()).collect();
+                                                    }
+                                                    settlement_game(g, &TotalUsers, &modifier, &msgtx, &sender, gm, &RankTiers, &mut Leaderboards, &mut Reviews)?;
+                                                }
+                                                if let Some(hero) = &gm.hero {
+                                                    if g.borrow().settled && !g.borrow().hero_rated {
+                                                        hero_rating(g, &x.users, &TotalUsers, gm, hero, &sender)?;
+                                                    }
+                                                }
+                                            }
                                             
                                             for u in &x.users {
                                                 let mut userinfo: UserInfoRes = Default::default();
@@ -2198,6 +3612,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     // SQL update
                                                     update_info.game = x.game.clone();
                                                     update_info.id = u.steamid.clone();
+                                                    update_info.mode = mode.clone();
                                                     update_info.hero = u.hero.clone();
                                                     if g.borrow().winteam.contains(&u.steamid) {
                                                         update_info.Res = true;
@@ -2205,7 +3620,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     if g.borrow().loseteam.contains(&u.steamid) {
                                                         update_info.Res = false;
                                                     }
-                                                    update_info.afk = false;
+                                                    update_info.afk = u.afk;
                                                     // for (i, e) in u.equ.iter().enumerate() {
                                                     //     update_info.equ += e;
                                                     //     if i < u.equ.len()-1 {
@@ -2218,6 +3633,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     update_info.D = u.D.clone();
                                                     update_info.A = u.A.clone();
                                                     update_info.BattleScore = 1;
+                                                    update_info.battle_score = u.BattleScore.parse().unwrap_or(0.0);
                                                     update_info.Talent = u.Talent.clone();
                                                     //println!("Update Info: {:?}", update_info);
                                                     sender.try_send(SqlData::UpdateGameInfo(update_info));
@@ -2243,9 +3659,20 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     userinfo.PlayerExperience = 20;
                                                     userinfo.PlayerLevel = u1.borrow().info.PlayerLv;
                                                     
-                                                    userinfo.Rank = "Gold".to_string();
-                                                    userinfo.LastRankScore = 25;
-                                                    userinfo.RankScore = 40;
+                                                    let res = match g.borrow().score_res.get(&u.steamid) {
+                                                        Some(res) => res.clone(),
+                                                        None => {
+                                                            // no settlement for this player, report the current rating
+                                                            let info = u1.borrow().rank.get(&g.borrow().mode).cloned().unwrap_or_default();
+                                                            let (tier, division) = get_tier(&RankTiers, info.score);
+                                                            ScoreRes {last_score: info.score, score: info.score, ranked: info.placement == 0,
+                                                                tier, division, ..Default::default()}
+                                                        }
+                                                    };
+                                                    userinfo.Rank = if res.ranked { res.tier.clone() } else { "Unranked".to_string() };
+                                                    userinfo.RankLevel = res.division;
+                                                    userinfo.LastRankScore = res.last_score.max(0) as u32;
+                                                    userinfo.RankScore = res.score.max(0) as u32;
                                                     // if g.borrow().winteam.contains(&u.steamid) {
                                                     //     userinfo.RankBattleCount = 15;
                                                     // } else {
@@ -2360,13 +3787,17 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                         if g.borrow().game_start == false {
                                             SendGameList(&g, &msgtx, &mut conn, &TotalEquip, &TotalEquOption);
                                             g.borrow_mut().game_start = true;
+                                            g.borrow_mut().start_time = time::get_time().sec;
                                             for r in &g.borrow().room_names {
                                                 if !isBackup || (isBackup && isServerLive == false) {
                                                     let s = TotalGameServer.iter().find(|&x| x.borrow().name == g.borrow().server_name);
                                                     if let Some(s) = s {
+                                                    // win_rate is the predicted win probability of the room's team
+                                                    let team = g.borrow().team_of(r);
+                                                    let win_rate = team.and_then(|t| g.borrow().expected.get(t).cloned()).unwrap_or(0.5);
                                                     msgtx.try_send(MqttMsg{topic:format!("room/{}/res/start", r), 
-                                                        msg: format!(r#"{{"room":"{}","msg":"start","server":"{}:{}","game":{}}}"#, 
-                                                            r, s.borrow().address, g.borrow().game_port, g.borrow().game_id), ..Default::default()})?;
+                                                        msg: format!(r#"{{"room":"{}","msg":"start","server":"{}:{}","game":{},"team":{},"win_rate":{}}}"#, 
+                                                            r, s.borrow().address, g.borrow().game_port, g.borrow().game_id, team.unwrap_or(0), win_rate), ..Default::default()})?;
                                                     }
                                                 }
                                             }
@@ -2401,10 +3832,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     if r.borrow().mode != "" {
                                                         msgtx.try_send(MqttMsg{topic:format!("room/{}/res/cancel_queue", m.clone()), 
                                                                 msg: format!(r#"{{"msg":"ok"}}"#), ..Default::default()});
-                                                        let t1 = QueueSender.get(&r.borrow().mode);
-                                                        if let Some(t1) = t1 {
-                                                            t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: u.borrow().rid}));
-                                                        }
+                                                        withdraw_queues(&QueueSender, &mut r.borrow_mut(), "");
                                                     }
                                                 }
                                                 mqttmsg = MqttMsg{topic:format!("room/{}/res/leave", x.id), 
@@ -2515,13 +3943,8 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                         for r in &gr.borrow().rooms {
                                                             //println!("r_rid: {}, u_rid: {}", r.borrow().rid, u.borrow().rid);
                                                             if r.borrow().rid != u.borrow().rid {
-                                                                let mut h: bool;
                                                                 println!("PRESTART room avg honor: {}",r.borrow().avg_honor.clone());
-                                                                if r.borrow().avg_honor.clone() < honor_threshold {
-                                                                    h = false;
-                                                                } else {
-                                                                    h = true;
-                                                                }
+                                                                let h = honor_cfg.tier(r.borrow().avg_honor);
                                                                 let mut data = QueueRoomData {
                                                                     user_name: users.clone(),
                                                                     hero: heros.clone(),
@@ -2536,6 +3959,8 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                                     queue_cnt: 1,
                                                                     block: block.clone(),
                                                                     blacklist: blacklist.clone(),
+                                                                    hero_mmr: get_hero_mmr(&r.borrow(), &gr.borrow().mode, &ModeCfg),
+                                                                    queue_time: r.borrow().queue_time,
                                                                 };
                                                                 if data.mode == "rk1p2t" || data.mode == "rk5p2t" {
                                                                     data.allow_same_hero = true;
@@ -2574,20 +3999,15 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                             let room = TotalRoom.get(&rid);
                                             if let Some(room) = room {
                                                 g.add_room(Rc::clone(&room));
-                                                if room.borrow().ready == 0 {
+                                                // a match of another mode already took the room
+                                                if room.borrow().ready == 0 || !room.borrow().queue_modes.contains(&x.mode) {
                                                     cancel_queue = true;
                                                 }
                                             }
                                         }
-                                        group_id += 1;
-                                        g.set_group_id(group_id);
                                         g.game_status = 1;
                                         g.mode = x.mode.clone();
-                                        ReadyGroups.insert(group_id, Rc::new(RefCell::new(g.clone())));
-                                        let mut rg = ReadyGroups.get(&group_id);
-                                        if let Some(rg) = rg {
-                                            fg.teams.push(Rc::clone(rg));
-                                        }
+                                        fg.teams.push(Rc::new(RefCell::new(g)));
                                     }
                                     
                                     fg.update_names();
@@ -2615,13 +4035,8 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 }
                                                 for r in &gr.borrow().rooms {
                                                     //println!("r_rid: {}, u_rid: {}", r.borrow().rid, u.borrow().rid);
-                                                    let mut h: bool;
                                                     println!("PRESTART room avg honor: {}",r.borrow().avg_honor.clone());
-                                                    if r.borrow().avg_honor.clone() < honor_threshold {
-                                                        h = false;
-                                                    } else {
-                                                        h = true;
-                                                    }
+                                                    let h = honor_cfg.tier(r.borrow().avg_honor);
                                                     let mut data = QueueRoomData {
                                                         user_name: users.clone(),
                                                         hero: heros.clone(),
@@ -2636,11 +4051,14 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                         queue_cnt: 1,
                                                         block: block.clone(),
                                                         blacklist: blacklist.clone(),
+                                                        hero_mmr: get_hero_mmr(&r.borrow(), &x.mode, &ModeCfg),
+                                                        queue_time: r.borrow().queue_time,
                                                     };
                                                     if data.mode == "rk1p2t" || data.mode == "rk5p2t" {
                                                         data.allow_same_hero = true;
                                                     }
-                                                    let t1 = QueueSender.get(&r.borrow().mode);
+                                                    // a room another match took stays out of this queue
+                                                    let t1 = QueueSender.get(&x.mode).filter(|_| r.borrow().queue_modes.contains(&x.mode));
                                                     if let Some(t1) = t1 {
                                                         t1.send(QueueData::UpdateRoom(data));
                                                     }
@@ -2648,13 +4066,19 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 
                                                 
                                             }
-                                            ReadyGroups.remove(&group_id);
-                                            ReadyGroups.remove(&(group_id-1));
                                         }
                                         else {
                                             println!("Prestart");
+                                            // only a kept match hands out group ids, a cancelled one leaves the users of another match alone
                                             for gr in &fg.teams {
+                                                group_id += 1;
+                                                gr.borrow_mut().set_group_id(group_id);
+                                                ReadyGroups.insert(group_id, Rc::clone(gr));
                                                 gr.borrow_mut().prestart();
+                                                for r in &gr.borrow().rooms {
+                                                    r.borrow_mut().mode = x.mode.clone();
+                                                    withdraw_queues(&QueueSender, &mut r.borrow_mut(), &x.mode);
+                                                }
                                             }
                                             for r in &fg.room_names {
                                                 //thread::sleep_ms(100);
@@ -2665,6 +4089,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                             fg.send = false;
                                             fg.done = false;
                                             fg.mode = x.mode.clone();
+                                            fg.update_expected();
                                             game_id += 1;
                                             fg.set_game_id(game_id);
                                             PreStartGroups.insert(game_id, Rc::new(RefCell::new(fg)));
@@ -2684,6 +4109,13 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                     let mut ban_t: u64 = 0;
                                     let u = TotalUsers.get(&x.id);
                                     let mut rid = 0;
+                                    // older clients send a single mode
+                                    let mut modes = x.modes.clone();
+                                    if modes.len() == 0 {
+                                        modes.push(x.mode.clone());
+                                    }
+                                    let mut seen: BTreeSet<String> = BTreeSet::new();
+                                    modes.retain(|m| seen.insert(m.clone()));
                                     if let Some(u) = u {
                                         if u.borrow().rid != 0 {
                                             hasRoom = true;
@@ -2700,7 +4132,11 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                     if hasRoom {
                                         let r = TotalRoom.get(&rid);
                                         if let Some(y) = r {
-                                            if (x.mode == "ng1p2t" || x.mode == "rk1p2t") && y.borrow_mut().users.len() > 1 {
+                                            if modes.iter().any(|m| !QueueSender.contains_key(m)) {
+                                                    mqttmsg = MqttMsg{topic:format!("room/{}/res/start_queue", y.borrow().master.clone()), 
+                                                    msg: format!(r#"{{"msg":"fail"}}"#), ..Default::default()};
+                                            }
+                                            else if modes.iter().any(|m| m == "ng1p2t" || m == "rk1p2t") && y.borrow_mut().users.len() > 1 {
                                                     mqttmsg = MqttMsg{topic:format!("room/{}/res/start_queue", y.borrow().master.clone()), 
                                                     msg: format!(r#"{{"msg":"fail"}}"#), ..Default::default()};
                                             }
@@ -2732,37 +4168,41 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                             msg: format!(r#"{{"msg":"ban", "time":{}}}"#, ban_t), ..Default::default()};
                                                 }
                                                 else {
-                                                    y.borrow_mut().mode = x.mode.clone();
+                                                    // queued again, the room leaves the queues it no longer asks for
+                                                    withdraw_queues(&QueueSender, &mut y.borrow_mut(), "");
+                                                    y.borrow_mut().mode = modes[0].clone();
+                                                    y.borrow_mut().queue_modes = modes.clone();
+                                                    y.borrow_mut().start_modes = modes.clone();
                                                     y.borrow_mut().ready = 1;
+                                                    y.borrow_mut().queue_time = time::get_time().sec;
                                                     y.borrow_mut().update_avg();
-                                                    let mut h: bool;
                                                     println!("STARTQUEUE room avg honor: {}",y.borrow().avg_honor.clone());
-                                                    if y.borrow().avg_honor.clone() < honor_threshold {
-                                                        h = false;
-                                                    } else {
-                                                        h = true;
-                                                    }
-                                                    let mut data = QueueRoomData {
-                                                        user_name: users.clone(),
-                                                        hero: heros.clone(),
-                                                        rid: y.borrow().rid.clone(),
-                                                        gid: 0,
-                                                        user_len: y.borrow().users.len().clone() as i16,
-                                                        avg: y.borrow().avg.clone(),
-                                                        honor: h,
-                                                        allow_same_hero: true,
-                                                        mode: x.mode.clone(),
-                                                        ready: 0,
-                                                        queue_cnt: 1,
-                                                        block: block.clone(),
-                                                        blacklist: blacklist.clone(),
-                                                    };
-                                                    if data.mode == "rk1p2t" || data.mode == "rk5p2t" {
-                                                        data.allow_same_hero = true;
-                                                    }
-                                                    let t1 = QueueSender.get(&x.mode.clone());
-                                                    if let Some(t1) = t1 {
-                                                        t1.send(QueueData::UpdateRoom(data));
+                                                    let h = honor_cfg.tier(y.borrow().avg_honor);
+                                                    for m in &modes {
+                                                        let mut data = QueueRoomData {
+                                                            user_name: users.clone(),
+                                                            hero: heros.clone(),
+                                                            rid: y.borrow().rid.clone(),
+                                                            gid: 0,
+                                                            user_len: y.borrow().users.len().clone() as i16,
+                                                            avg: y.borrow().avg.clone(),
+                                                            honor: h,
+                                                            allow_same_hero: true,
+                                                            mode: m.clone(),
+                                                            ready: 0,
+                                                            queue_cnt: 1,
+                                                            block: block.clone(),
+                                                            blacklist: blacklist.clone(),
+                                                            hero_mmr: get_hero_mmr(&y.borrow(), m, &ModeCfg),
+                                                            queue_time: y.borrow().queue_time,
+                                                        };
+                                                        if data.mode == "rk1p2t" || data.mode == "rk5p2t" {
+                                                            data.allow_same_hero = true;
+                                                        }
+                                                        let t1 = QueueSender.get(m);
+                                                        if let Some(t1) = t1 {
+                                                            t1.send(QueueData::UpdateRoom(data));
+                                                        }
                                                     }
                                                     success = true;
                                                     if success {
@@ -2798,11 +4238,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                         if let Some(r) = r {
                                             println!("room master: {}", r.borrow().master);
                                             r.borrow_mut().ready = 0;
-                                            let t1 = QueueSender.get(&r.borrow().mode);
-                                            //println!("mode: {}", &r.borrow().mode);
-                                            if let Some(t1) = t1 {
-                                                t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: u.borrow().rid}));
-                                            }
+                                            withdraw_queues(&QueueSender, &mut r.borrow_mut(), "");
                                         }
                                         if let Some(r) = r {
                                             success = true;
@@ -2854,10 +4290,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                             ReadyGroups.remove(&gid);
                                                             let r = TotalRoom.get(&rid);
                                                             if let Some(r) = r {
-                                                                let t1 = QueueSender.get(&r.borrow().mode);
-                                                                if let Some(t1) = t1 {
-                                                                    t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: rid}));
-                                                                }
+                                                                withdraw_queues(&QueueSender, &mut r.borrow_mut(), "");
                                                                 if !isBackup || (isBackup && isServerLive == false) {
                                                                     msgtx.try_send(MqttMsg{topic:format!("room/{}/res/cancel_queue", r.borrow().master), 
                                                                         msg: format!(r#"{{"msg":"ok","id":"{}"}}"#, x.u.id), ..Default::default()})?;
@@ -2885,6 +4318,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 // re-Login
                                                 msgtx.try_send(MqttMsg{topic:format!("member/{}/res/login", u2.borrow().id.clone()), 
                                                         msg: format!(r#"{{"msg":"ok","id":"{}"}}"#, u2.borrow().id.clone()), ..Default::default()})?;
+                                                decay_notice(u2, &msgtx, &sender)?;
                                             } else {
                                             u2.borrow_mut().online = true;
                                                 if !isBackup || (isBackup && isServerLive == false) {
@@ -2894,6 +4328,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
 
                                                 mqttmsg = MqttMsg{topic:format!("member/{}/res/score", u2.borrow().id.clone()), 
                                                     msg: json!(u2.borrow().rank).to_string(), ..Default::default()};
+                                                decay_notice(u2, &msgtx, &sender)?;
                                             }
                                         }
                                         
@@ -2902,6 +4337,11 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                         let mut user = x.u.clone();
                                         user.info.PlayerExp = 0;
                                         user.info.PlayerLv = 1;
+                                        for (m, info) in user.rank.iter_mut() {
+                                            if let Some(gm) = ModeCfg.get(m) {
+                                                *info = gm.new_score();
+                                            }
+                                        }
                                         for h in hero.clone() {
                                             let hero = Hero {
                                                 Hero_name: h.clone(),
@@ -2918,7 +4358,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 msg: format!(r#"{{"msg":"ok","id":"{}"}}"#, x.u.id.clone()), ..Default::default()})?;
                                         }
                                         mqttmsg = MqttMsg{topic:format!("member/{}/res/score", x.u.id.clone()), 
-                                            msg: json!(x.u.rank).to_string(), ..Default::default()};
+                                            msg: json!(user.rank).to_string(), ..Default::default()};
                                     }
                                 },
                                 RoomEventData::Logout(x) => {
@@ -2967,10 +4407,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                     //println!("Totalroom rid: {}", &u.borrow().rid);
                                                     
                                                     if let Some(r) = r {
-                                                        let t1 = QueueSender.get(&r.borrow().mode);
-                                                        if let Some(t1) = t1 {
-                                                            t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: rid}));
-                                                        }
+                                                        withdraw_queues(&QueueSender, &mut r.borrow_mut(), "");
                                                         if !isBackup || (isBackup && isServerLive == false) {
                                                             msgtx.try_send(MqttMsg{topic:format!("room/{}/res/cancel_queue", r.borrow().master), 
                                                                 msg: format!(r#"{{"msg":"ok"}}"#), ..Default::default()})?;
@@ -3024,6 +4461,9 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                                 avg_honor: u.borrow().honor,
                                                 ready: 0,
                                                 queue_cnt: 1,
+                                                queue_time: 0,
+                                                queue_modes: vec![],
+                                                start_modes: vec![],
                                                 avg: BTreeMap::new(),
                                                 modes: Rc::new(RefCell::new(modes.clone())),
                                             };
@@ -3055,10 +4495,7 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                             Some(_) => {
                                                 println!("Totalroom rid: {}", &get_rid_by_id(&x.id, &TotalUsers));
                                                 if y.borrow().mode != "" {
-                                                    let t1 = QueueSender.get(&y.borrow().mode);
-                                                    if let Some(t1) = t1 {
-                                                        t1.send(QueueData::RemoveRoom(RemoveRoomData{rid: get_rid_by_id(&x.id, &TotalUsers)}));
-                                                    }
+                                                    withdraw_queues(&QueueSender, &mut y.borrow_mut(), "");
                                                 }
                                                 success = true;
                                             },
@@ -3474,6 +4911,83 @@ pub fn init(msgtx: Sender<MqttMsg>, sender: Sender<SqlData>, pool: mysql::Pool,
                                             msg: format!(r#"{{"list":{:?}}}"#, u.borrow().blacklist.clone()), ..Default::default()};
                                     }
                                 },
+                                RoomEventData::SeasonStandings(x) => {
+                                    // only a configured mode goes on to the SQL thread
+                                    if modes.contains(&x.mode) {
+                                        sender.try_send(SqlData::SeasonStandings(x))?;
+                                    } else {
+                                        mqttmsg = MqttMsg{topic:format!("member/{}/res/season_standings", x.id.clone()), 
+                                            msg: format!(r#"{{"msg":"mode not found"}}"#), ..Default::default()};
+                                    }
+                                },
+                                RoomEventData::LeaderboardQuery(x) => {
+                                    if let Some(lb) = Leaderboards.get(&x.mode) {
+                                        let res = LeaderboardRes {
+                                            mode: x.mode.clone(),
+                                            total: lb.len(),
+                                            position: lb.position(&x.id),
+                                            top: lb.top(x.top.unwrap_or(10).min(leaderboard_size)),
+                                            around: lb.around(&x.id, x.around.unwrap_or(5).min(leaderboard_size)),
+                                        };
+                                        mqttmsg = MqttMsg{topic:format!("member/{}/res/leaderboard", x.id.clone()), 
+                                            msg: json!(res).to_string(), ..Default::default()};
+                                    } else {
+                                        mqttmsg = MqttMsg{topic:format!("member/{}/res/leaderboard", x.id.clone()), 
+                                            msg: format!(r#"{{"msg":"mode not found"}}"#), ..Default::default()};
+                                    }
+                                },
+                                RoomEventData::RatingHistory(x) => {
+                                    // only a configured mode goes on to the SQL thread
+                                    if x.mode.as_ref().map_or(true, |m| modes.contains(m)) {
+                                        sender.try_send(SqlData::RatingHistory(x))?;
+                                    } else {
+                                        mqttmsg = MqttMsg{topic:format!("member/{}/res/rating_history", x.id.clone()), 
+                                            msg: format!(r#"{{"msg":"mode not found"}}"#), ..Default::default()};
+                                    }
+                                },
+                                RoomEventData::ReviewList(x) => {
+                                    // only a configured mode goes on to the SQL thread
+                                    if x.mode.as_ref().map_or(true, |m| modes.contains(m)) {
+                                        sender.try_send(SqlData::ReviewList(x))?;
+                                    } else {
+                                        mqttmsg = MqttMsg{topic:format!("manager/0/res/review_list"), 
+                                            msg: format!(r#"{{"msg":"mode not found"}}"#), ..Default::default()};
+                                    }
+                                },
+                                RoomEventData::ReviewResolve(x) => {
+                                    let mut res = ReviewResolveRes {no: x.no, msg: "not found".to_string()};
+                                    match resolve_review(&mut conn, x.no, x.confirmed) {
+                                        Ok(Some((mode, id, other, reason, 0))) => {
+                                            // a player stays frozen while another flag of the mode is open
+                                            let mut open: Vec<&String> = vec![];
+                                            if let Some(review) = Reviews.get_mut(&mode) {
+                                                review.resolve(&id, &other, &reason);
+                                                open = [&id, &other].iter().filter(|x| review.has_open(x)).cloned().collect();
+                                            }
+                                            for id in &[&id, &other] {
+                                                if open.contains(id) {
+                                                    continue;
+                                                }
+                                                if let Some(u) = TotalUsers.get(*id) {
+                                                    if let Some(info) = u.borrow_mut().rank.get_mut(&mode) {
+                                                        info.frozen = false;
+                                                    }
+                                                }
+                                            }
+                                            res.msg = "ok".to_string();
+                                        }
+                                        Ok(Some(_)) => {
+                                            res.msg = "already resolved".to_string();
+                                        }
+                                        Ok(None) => {}
+                                        Err(e) => {
+                                            println!("review_resolve {:?}", e);
+                                            res.msg = "fail".to_string();
+                                        }
+                                    }
+                                    mqttmsg = MqttMsg{topic:format!("manager/0/res/review_resolve"), 
+                                        msg: json!(res).to_string(), ..Default::default()};
+                                },
                                 RoomEventData::RemoveBlackList(x) => {
                                     //println!("Remove Black List");
                                     let mut success = false;
@@ -3673,7 +5187,7 @@ pub fn start_queue(id: String, v: Value, sender: Sender<RoomEventData>)
  -> std::result::Result<(), Error>
 {
     let data: StartQueueData = serde_json::from_value(v)?;
-    sender.try_send(RoomEventData::StartQueue(StartQueueData{id: data.id.clone(), action: data.action.clone(), mode: data.mode.clone()}));
+    sender.try_send(RoomEventData::StartQueue(StartQueueData{id: data.id.clone(), action: data.action.clone(), mode: data.mode.clone(), modes: data.modes.clone()}));
     Ok(())
 }
 
@@ -3928,6 +5442,49 @@ pub fn query_black_list(id: String, v: Value, sender: Sender<RoomEventData>)
     Ok(())
 }
 
+pub fn season_standings(id: String, v: Value, sender: Sender<RoomEventData>)
+ -> std::result::Result<(), Error>
+{
+    let mut data: SeasonStandingsData = serde_json::from_value(v)?;
+    data.id = id;
+    sender.try_send(RoomEventData::SeasonStandings(data));
+    Ok(())
+}
+
+pub fn leaderboard_query(mode: String, v: Value, sender: Sender<RoomEventData>)
+ -> std::result::Result<(), Error>
+{
+    let mut data: LeaderboardQueryData = serde_json::from_value(v)?;
+    data.mode = mode;
+    sender.try_send(RoomEventData::LeaderboardQuery(data));
+    Ok(())
+}
+
+pub fn rating_history(id: String, v: Value, sender: Sender<RoomEventData>)
+ -> std::result::Result<(), Error>
+{
+    let mut data: RatingHistoryData = serde_json::from_value(v)?;
+    data.id = id;
+    sender.try_send(RoomEventData::RatingHistory(data));
+    Ok(())
+}
+
+pub fn review_list(id: String, v: Value, sender: Sender<RoomEventData>)
+ -> std::result::Result<(), Error>
+{
+    let data: ReviewListData = serde_json::from_value(v)?;
+    sender.try_send(RoomEventData::ReviewList(data));
+    Ok(())
+}
+
+pub fn review_resolve(id: String, v: Value, sender: Sender<RoomEventData>)
+ -> std::result::Result<(), Error>
+{
+    let data: ReviewResolveData = serde_json::from_value(v)?;
+    sender.try_send(RoomEventData::ReviewResolve(data));
+    Ok(())
+}
+
 pub fn remove_black_list(id: String, v: Value, sender: Sender<RoomEventData>)
  -> std::result::Result<(), Error>
 {
@@ -0,0 +1,126 @@
+use serde_derive::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::{HashMap, BTreeMap};
+use crate::room::*;
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct LeaderboardEntry {
+    pub position: usize,
+    pub id: String,
+    pub score: i32,
+    pub WinCount: u32,
+    pub LoseCount: u32,
+}
+
+// ranked players of one mode, sorted by score desc then id
+#[derive(Clone, Debug, Default)]
+pub struct Leaderboard {
+    pub mode: String,
+    list: Vec<LeaderboardEntry>,
+    scores: HashMap<String, i32>,
+}
+
+impl Leaderboard {
+    pub fn new(mode: String) -> Leaderboard {
+        Leaderboard {mode: mode, ..Default::default()}
+    }
+
+    pub fn len(&self) -> usize {
+        self.list.len()
+    }
+
+    fn find(&self, id: &String, score: i32) -> Result<usize, usize> {
+        self.list.binary_search_by(|x| score.cmp(&x.score).then(x.id.cmp(id)))
+    }
+
+    pub fn remove(&mut self, id: &String) {
+        if let Some(score) = self.scores.remove(id) {
+            if let Ok(i) = self.find(id, score) {
+                self.list.remove(i);
+            }
+        }
+    }
+
+    // players still in placement or without a game in this mode are not listed
+    pub fn update(&mut self, u: &User) {
+        self.remove(&u.id);
+        if let Some(info) = u.rank.get(&self.mode) {
+            if info.placement > 0 || info.WinCount + info.LoseCount == 0 {
+                return;
+            }
+            let e = LeaderboardEntry {
+                position: 0,
+                id: u.id.clone(),
+                score: info.score,
+                WinCount: info.WinCount,
+                LoseCount: info.LoseCount,
+            };
+            if let Err(i) = self.find(&e.id, e.score) {
+                self.list.insert(i, e);
+                self.scores.insert(u.id.clone(), info.score);
+            }
+        }
+    }
+
+    pub fn rebuild(&mut self, users: &BTreeMap<String, Rc<RefCell<User>>>) {
+        self.list.clear();
+        self.scores.clear();
+        for (id, u) in users {
+            self.update(&u.borrow());
+        }
+    }
+
+    // 1-based position of the player
+    pub fn position(&self, id: &String) -> Option<usize> {
+        let score = self.scores.get(id)?;
+        self.find(id, *score).ok().map(|i| i+1)
+    }
+
+    fn page(&self, start: usize, end: usize) -> Vec<LeaderboardEntry> {
+        let end = end.min(self.list.len());
+        let start = start.min(end);
+        self.list[start..end].iter().enumerate()
+            .map(|(i, x)| LeaderboardEntry {position: start+i+1, ..x.clone()})
+            .collect()
+    }
+
+    pub fn top(&self, n: usize) -> Vec<LeaderboardEntry> {
+        self.page(0, n)
+    }
+
+    // n players above and below the player
+    pub fn around(&self, id: &String, n: usize) -> Vec<LeaderboardEntry> {
+        match self.position(id) {
+            Some(p) => self.page((p-1).saturating_sub(n), p+n),
+            None => vec![],
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_leaderboard() {
+        let mut users: BTreeMap<String, Rc<RefCell<User>>> = BTreeMap::new();
+        for (i, score) in [1200, 900, 1500, 1000, 1100].iter().enumerate() {
+            let mut u = User {id: format!("u{}", i), ..Default::default()};
+            u.rank.insert("rk5p2t".to_string(), ScoreInfo {score: *score, WinCount: 1, ..Default::default()});
+            users.insert(u.id.clone(), Rc::new(RefCell::new(u)));
+        }
+        let mut lb = Leaderboard::new("rk5p2t".to_string());
+        lb.rebuild(&users);
+        let top: Vec<String> = lb.top(3).iter().map(|x| x.id.clone()).collect();
+        assert_eq!(top, vec!["u2", "u0", "u4"]);
+        assert_eq!(lb.position(&"u1".to_string()), Some(5));
+
+        users["u1"].borrow_mut().rank.get_mut("rk5p2t").unwrap().score = 1300;
+        lb.update(&users["u1"].borrow());
+        assert_eq!(lb.len(), 5);
+        assert_eq!(lb.position(&"u1".to_string()), Some(2));
+        let around: Vec<usize> = lb.around(&"u1".to_string(), 1).iter().map(|x| x.position).collect();
+        assert_eq!(around, vec![1, 2, 3]);
+    }
+}
@@ -6,6 +6,9 @@ mod event_room;
 mod room;
 mod msg;
 mod elo;
+mod leaderboard;
+mod review;
+mod queue;
 
 use std::cell::RefCell;
 use std::rc::Rc;
@@ -190,6 +193,8 @@ fn main() -> std::result::Result<(), Error> {
 
 
     mqtt_client.subscribe("manager/+/send/equ_test", QoS::AtMostOnce).unwrap();
+    mqtt_client.subscribe("manager/+/send/review_list", QoS::AtMostOnce).unwrap();
+    mqtt_client.subscribe("manager/+/send/review_resolve", QoS::AtMostOnce).unwrap();
     // User Equipment
     mqtt_client.subscribe("manager/+/send/insert_equ", QoS::AtMostOnce).unwrap();
     mqtt_client.subscribe("manager/+/send/modify_userequ", QoS::AtMostOnce).unwrap();
@@ -217,6 +222,9 @@ fn main() -> std::result::Result<(), Error> {
     mqtt_client.subscribe("member/+/send/query_black_list", QoS::AtMostOnce).unwrap();
     mqtt_client.subscribe("member/+/send/remove_black_list", QoS::AtMostOnce).unwrap();
     mqtt_client.subscribe("member/+/send/talent", QoS::AtMostOnce).unwrap();
+    mqtt_client.subscribe("member/+/send/season_standings", QoS::AtMostOnce).unwrap();
+    mqtt_client.subscribe("member/+/send/rating_history", QoS::AtMostOnce).unwrap();
+    mqtt_client.subscribe("leaderboard/+/send/query", QoS::AtMostOnce).unwrap();
 
 
     mqtt_client.subscribe("room/+/send/create", QoS::AtMostOnce).unwrap();
@@ -363,6 +371,9 @@ fn main() -> std::result::Result<(), Error> {
     let readd_black_list = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/add_black_list").unwrap();
     let requery_black_list = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/query_black_list").unwrap();
     let reremove_black_list = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/remove_black_list").unwrap();
+    let reseason_standings = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/season_standings").unwrap();
+    let rerating_history = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/rating_history").unwrap();
+    let releaderboard = Regex::new(r"leaderboard/(((\w+)(\-)*)+)/send/query$").unwrap();
     let recreate = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/create").unwrap();
     let reclose = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/close").unwrap();
     let restart_queue = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/start_queue").unwrap();
@@ -389,6 +400,8 @@ fn main() -> std::result::Result<(), Error> {
     let rerankgame_status = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/rankgame_status").unwrap();
 
     let reequ_test = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/equ_test").unwrap();
+    let rereview_list = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/review_list").unwrap();
+    let rereview_resolve = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/review_resolve").unwrap();
     // User Equipment
     let reinsert_equ = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/insert_equ").unwrap();
     let remodify_userequ = Regex::new(r"\w+/(((\w+)(\-)*)+)/send/modify_userequ").unwrap();
@@ -438,7 +451,7 @@ fn main() -> std::result::Result<(), Error> {
 
 
     //let mut QueueSender: Sender<QueueData>;
-    let mut sender1: Sender<SqlData> = event_room::HandleSqlRequest(pool.clone())?;
+    let mut sender1: Sender<SqlData> = event_room::HandleSqlRequest(tx.clone(), pool.clone())?;
     let mut sender: Sender<RoomEventData> = event_room::init(tx.clone(), sender1.clone(), pool.clone(), server_addr.clone(), isBackup)?;
     let update = tick(Duration::from_millis(500));
     let mut is_live = true;
@@ -463,11 +476,7 @@ fn main() -> std::result::Result<(), Error> {
     let block_recent_player_of_games = config.game_setting.clone().unwrap().BLOCK_RECENT_PLAYER_OF_GAMES.unwrap();
     let mut ModeCfg: BTreeMap<String, GameModeCfg> = BTreeMap::new();
     for x in config.game_mode.unwrap() {
-        let gmc = GameModeCfg {
-            mode: x.MODE.clone().unwrap(),
-            team_size: x.TEAM_SIZE.unwrap(),
-            match_size: x.MATCH_SIZE.unwrap()
-        };
+        let gmc = GameModeCfg::new(&x);
         ModeCfg.insert(x.MODE.clone().unwrap(), gmc);
     }
     let modes: Vec<String> = ModeCfg.into_iter().map(|(k, v)| k).collect();
@@ -719,10 +728,30 @@ fn main() -> std::result::Result<(), Error> {
                                     let cap = reremove_black_list.captures(topic_name).unwrap();
                                     let userid = cap[1].to_string();
                                     event_room::remove_black_list(userid, v, sender.clone())?;
+                                } else if reseason_standings.is_match(topic_name) {
+                                    let cap = reseason_standings.captures(topic_name).unwrap();
+                                    let userid = cap[1].to_string();
+                                    event_room::season_standings(userid, v, sender.clone())?;
+                                } else if rerating_history.is_match(topic_name) {
+                                    let cap = rerating_history.captures(topic_name).unwrap();
+                                    let userid = cap[1].to_string();
+                                    event_room::rating_history(userid, v, sender.clone())?;
+                                } else if releaderboard.is_match(topic_name) {
+                                    let cap = releaderboard.captures(topic_name).unwrap();
+                                    let mode = cap[1].to_string();
+                                    event_room::leaderboard_query(mode, v, sender.clone())?;
                                 } else if reequ_test.is_match(topic_name) {
                                     let cap = reequ_test.captures(topic_name).unwrap();
                                     let userid = cap[1].to_string();
                                     event_room::equ_test(userid, v, sender.clone())?;
+                                } else if rereview_list.is_match(topic_name) {
+                                    let cap = rereview_list.captures(topic_name).unwrap();
+                                    let userid = cap[1].to_string();
+                                    event_room::review_list(userid, v, sender.clone())?;
+                                } else if rereview_resolve.is_match(topic_name) {
+                                    let cap = rereview_resolve.captures(topic_name).unwrap();
+                                    let userid = cap[1].to_string();
+                                    event_room::review_resolve(userid, v, sender.clone())?;
                                 }
                             } else {
                                 warn!("Json Parser error");
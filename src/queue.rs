@@ -0,0 +1,522 @@
+use serde_derive::{Serialize, Deserialize};
+use std::cell::RefCell;
+use std::rc::Rc;
+use std::collections::{BTreeMap, BTreeSet};
+use crate::event_room::{QueueRoomData, ReadyGroupData, ReadyGameData};
+
+// search window by wait time: initial, plus growth per second, never above cap
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct WidenCfg {
+    pub initial: i32,
+    pub growth: f32,
+    pub cap: i32,
+}
+
+impl WidenCfg {
+    pub fn window(&self, queue_time: i64, now: i64) -> i32 {
+        let wait = (now - queue_time).max(0) as f32;
+        ((self.initial as f32 + self.growth * wait) as i32).min(self.cap)
+    }
+}
+
+// honor tiers by ascending thresholds. below the first threshold is the low priority tier,
+// it waits delay seconds before matching and only meets itself. the other tiers take rooms
+// of the adjacent tiers once the anchor or the candidate has waited merge_wait seconds
+#[derive(Serialize, Deserialize, Clone, Debug, Default)]
+pub struct HonorCfg {
+    pub tiers: Vec<i32>,
+    pub delay: i64,
+    pub merge_wait: i64,
+}
+
+impl HonorCfg {
+    pub fn tier(&self, honor: i32) -> u8 {
+        self.tiers.iter().filter(|t| honor >= **t).count() as u8
+    }
+
+    fn low(&self, tier: u8) -> bool {
+        tier == 0 && self.tiers.len() > 0
+    }
+
+    pub fn ready(&self, tier: u8, queue_time: i64, now: i64) -> bool {
+        !self.low(tier) || now - queue_time >= self.delay
+    }
+
+    pub fn fits(&self, anchor: u8, queue_time: i64, tier: u8, tier_time: i64, now: i64) -> bool {
+        if tier == anchor {
+            return true;
+        }
+        !self.low(anchor) && !self.low(tier) && (tier == anchor + 1 || tier + 1 == anchor)
+            && now - queue_time.min(tier_time) >= self.merge_wait
+    }
+}
+
+// the queue of one mode. rooms outside a group are indexed by party size then rating and
+// ready groups by rating, so matching only walks the candidates inside the search window
+#[derive(Debug, Default)]
+pub struct QueueIndex {
+    pub mode: String,
+    pub team_size: i16,
+    pub match_size: usize,
+    pub widen: WidenCfg,
+    // re-split the rooms of a matched game into the fairest teams
+    pub balance: bool,
+    pub honor: HonorCfg,
+    pub rooms: BTreeMap<u32, Rc<RefCell<QueueRoomData>>>,
+    pub groups: BTreeMap<u32, Rc<RefCell<ReadyGroupData>>>,
+    // party size -> (rating, rid) of rooms not in a group
+    free: BTreeMap<i16, BTreeSet<(i32, u32)>>,
+    // (rating, gid) of groups waiting for a game
+    ready: BTreeSet<(i32, u32)>,
+    group_id: u32,
+}
+
+fn get_score(avg: &BTreeMap<String, i32>, mode: &String) -> i32 {
+    avg.get(mode).cloned().unwrap_or(0)
+}
+
+// nearest key to score within window on either side that passes check
+fn nearest<F>(set: &BTreeSet<(i32, u32)>, score: i32, window: i32, mut check: F) -> Option<(i32, u32)>
+    where F: FnMut(u32) -> bool {
+    let up = set.range((score, 0)..=(score.saturating_add(window), u32::max_value())).find(|x| check(x.1)).cloned();
+    let down = set.range((score.saturating_sub(window), 0)..(score, 0)).rev().find(|x| check(x.1)).cloned();
+    match (up, down) {
+        (Some(u), Some(d)) => if u.0 - score <= score - d.0 { Some(u) } else { Some(d) },
+        (u, d) => u.or(d),
+    }
+}
+
+fn room_fits(g: &ReadyGroupData, v: &QueueRoomData) -> bool {
+    if g.user_len == 0 {
+        return true;
+    }
+    if v.user_name.iter().any(|u| g.blacklist.contains(u) || g.block.contains(u)) {
+        return false;
+    }
+    if g.user_name.iter().any(|u| v.blacklist.contains(u) || v.block.contains(u)) {
+        return false;
+    }
+    g.allow_same_hero || !g.hero.iter().any(|h| v.hero.contains(h))
+}
+
+// rooms that may play on the same team
+fn can_team(a: &QueueRoomData, b: &QueueRoomData) -> bool {
+    if a.user_name.iter().any(|u| b.blacklist.contains(u)) || b.user_name.iter().any(|u| a.blacklist.contains(u)) {
+        return false;
+    }
+    (a.allow_same_hero && b.allow_same_hero) || !a.hero.iter().any(|h| b.hero.contains(h))
+}
+
+// rating sum and members of one team while searching splits
+#[derive(Clone, Default)]
+struct Split {
+    len: i16,
+    sum: i32,
+    rooms: Vec<usize>,
+}
+
+fn search_split(rooms: &Vec<(i16, i32, Rc<RefCell<QueueRoomData>>)>, i: usize, team_size: i16, teams: &mut Vec<Split>, best: &mut Option<(i32, Vec<Split>)>) {
+    if best.as_ref().map_or(false, |b| b.0 == 0) {
+        return;
+    }
+    if i == rooms.len() {
+        let gap = teams.iter().map(|t| t.sum).max().unwrap_or(0) - teams.iter().map(|t| t.sum).min().unwrap_or(0);
+        if best.as_ref().map_or(true, |b| gap < b.0) {
+            *best = Some((gap, teams.clone()));
+        }
+        return;
+    }
+    let (size, score, room) = &rooms[i];
+    for t in 0..teams.len() {
+        // empty teams are interchangeable, only the first one is tried
+        if teams[t].len == 0 && teams[..t].iter().any(|x| x.len == 0) {
+            break;
+        }
+        if teams[t].len + size > team_size || !teams[t].rooms.iter().all(|j| can_team(&room.borrow(), &rooms[*j].2.borrow())) {
+            continue;
+        }
+        teams[t].len += size;
+        teams[t].sum += score * *size as i32;
+        teams[t].rooms.push(i);
+        search_split(rooms, i+1, team_size, teams, best);
+        teams[t].rooms.pop();
+        teams[t].sum -= score * *size as i32;
+        teams[t].len -= size;
+    }
+}
+
+fn group_fits(fg: &ReadyGameData, rg: &ReadyGroupData) -> bool {
+    if rg.game_status != 0 {
+        return false;
+    }
+    if fg.team_len == 0 {
+        return true;
+    }
+    if rg.user_name.iter().any(|u| fg.block.contains(u)) || fg.user_name.iter().any(|u| rg.block.contains(u)) {
+        return false;
+    }
+    fg.allow_same_hero || !fg.hero.iter().any(|h| rg.hero.contains(h))
+}
+
+impl QueueIndex {
+    pub fn new(mode: String, team_size: i16, match_size: usize, widen: WidenCfg, balance: bool, honor: HonorCfg) -> QueueIndex {
+        QueueIndex {mode: mode, team_size: team_size, match_size: match_size, widen: widen, balance: balance, honor: honor, ..Default::default()}
+    }
+
+    // every split of the rooms into match_size full teams that keeps parties whole, the one with
+    // the smallest gap between the strongest and weakest team average wins, teams are equal in
+    // size so the rating sums compare the same way. None when no split keeps blacklists apart
+    pub fn balance_teams(&self, group: &Vec<Vec<u32>>) -> Option<Vec<Vec<u32>>> {
+        let mut rooms: Vec<(i16, i32, Rc<RefCell<QueueRoomData>>)> = group.iter().flatten()
+            .filter_map(|rid| self.rooms.get(rid))
+            .map(|r| (r.borrow().user_len, get_score(&r.borrow().avg, &self.mode), Rc::clone(r)))
+            .collect();
+        // large parties first cut the search early
+        rooms.sort_by(|a, b| b.0.cmp(&a.0).then(b.1.cmp(&a.1)));
+        let mut best = None;
+        search_split(&rooms, 0, self.team_size, &mut vec![Split::default(); self.match_size], &mut best);
+        best.map(|(_, teams)| teams.iter().map(|t| t.rooms.iter().map(|i| rooms[*i].2.borrow().rid).collect()).collect())
+    }
+
+    fn room_key(&self, r: &QueueRoomData) -> (i16, (i32, u32)) {
+        (r.user_len, (get_score(&r.avg, &self.mode), r.rid))
+    }
+
+    fn index_room(&mut self, rid: u32) {
+        if let Some(r) = self.rooms.get(&rid) {
+            let (size, key) = self.room_key(&r.borrow());
+            self.free.entry(size).or_default().insert(key);
+        }
+    }
+
+    fn unindex_room(&mut self, rid: u32) {
+        if let Some(r) = self.rooms.get(&rid) {
+            let (size, key) = self.room_key(&r.borrow());
+            if let Some(set) = self.free.get_mut(&size) {
+                set.remove(&key);
+            }
+        }
+    }
+
+    pub fn insert_room(&mut self, room: QueueRoomData) {
+        let rid = room.rid;
+        self.remove_room(rid);
+        self.rooms.insert(rid, Rc::new(RefCell::new(room)));
+        self.index_room(rid);
+    }
+
+    // a room leaving breaks up its ready group, the other rooms go back to the index
+    pub fn remove_room(&mut self, rid: u32) {
+        let gid = match self.rooms.get(&rid) {
+            Some(r) => r.borrow().gid,
+            None => return,
+        };
+        if let Some(rg) = self.groups.remove(&gid) {
+            self.ready.remove(&(get_score(&rg.borrow().avg, &self.mode), gid));
+            for r in &rg.borrow().rid {
+                if *r == rid {
+                    continue;
+                }
+                if let Some(room) = self.rooms.get(r) {
+                    room.borrow_mut().gid = 0;
+                    room.borrow_mut().ready = 0;
+                }
+                self.index_room(*r);
+            }
+        }
+        self.unindex_room(rid);
+        self.rooms.remove(&rid);
+    }
+
+    pub fn free_len(&self) -> usize {
+        self.free.values().map(|x| x.len()).sum()
+    }
+
+    fn add_room(&self, g: &mut ReadyGroupData, v: &QueueRoomData) {
+        let group_score = get_score(&g.avg, &self.mode);
+        let room_score = get_score(&v.avg, &self.mode);
+        if g.user_len == 0 {
+            g.allow_same_hero = v.allow_same_hero;
+            g.honor = v.honor;
+            g.queue_time = v.queue_time;
+        }
+        g.queue_time = g.queue_time.min(v.queue_time);
+        g.rid.push(v.rid);
+        g.hero.extend(v.hero.iter().cloned());
+        g.block.extend(v.block.iter().cloned());
+        g.blacklist.extend(v.blacklist.iter().cloned());
+        g.user_name.extend(v.user_name.iter().cloned());
+        let score = (group_score * g.user_len as i32 + room_score * v.user_len as i32) / (g.user_len + v.user_len) as i32;
+        g.avg.insert(self.mode.clone(), score);
+        g.max_room_len = g.max_room_len.max(v.user_len);
+        g.user_len += v.user_len;
+    }
+
+    // every free room anchors a group once, largest parties first, and takes the nearest
+    // fitting room of the largest party that still fits until the team is full
+    pub fn match_groups(&mut self, now: i64) {
+        if self.rooms.len() < self.match_size {
+            return;
+        }
+        let mut anchors: Vec<(i16, (i32, u32))> = vec![];
+        for (size, set) in self.free.iter().rev() {
+            anchors.extend(set.iter().map(|x| (*size, *x)));
+        }
+        for (size, key) in anchors {
+            if !self.free.get(&size).map_or(false, |x| x.contains(&key)) {
+                continue;
+            }
+            let anchor = Rc::clone(&self.rooms[&key.1]);
+            let (tier, queue_time) = (anchor.borrow().honor, anchor.borrow().queue_time);
+            if !self.honor.ready(tier, queue_time, now) {
+                continue;
+            }
+            let window = self.widen.window(queue_time, now);
+            let mut g: ReadyGroupData = Default::default();
+            self.add_room(&mut g, &anchor.borrow());
+            self.unindex_room(key.1);
+            let mut members = vec![key.1];
+            while g.user_len < self.team_size {
+                let score = get_score(&g.avg, &self.mode);
+                let mut found = None;
+                for s in (1..=self.team_size - g.user_len).rev() {
+                    if let Some(set) = self.free.get(&s) {
+                        let (rooms, honor) = (&self.rooms, &self.honor);
+                        found = nearest(set, score, window, |rid| {
+                            let v = rooms[&rid].borrow();
+                            honor.ready(v.honor, v.queue_time, now) && honor.fits(tier, queue_time, v.honor, v.queue_time, now) && room_fits(&g, &v)
+                        });
+                        if found.is_some() {
+                            break;
+                        }
+                    }
+                }
+                match found {
+                    Some((_, rid)) => {
+                        let v = Rc::clone(&self.rooms[&rid]);
+                        self.add_room(&mut g, &v.borrow());
+                        self.unindex_room(rid);
+                        members.push(rid);
+                    }
+                    None => break,
+                }
+            }
+            if g.user_len == self.team_size {
+                self.group_id += 1;
+                g.gid = self.group_id;
+                g.queue_cnt = 1;
+                for rid in &members {
+                    let mut r = self.rooms[rid].borrow_mut();
+                    r.ready = 1;
+                    r.gid = g.gid;
+                }
+                self.ready.insert((get_score(&g.avg, &self.mode), g.gid));
+                self.groups.insert(g.gid, Rc::new(RefCell::new(g)));
+            } else {
+                for rid in members {
+                    self.index_room(rid);
+                }
+            }
+        }
+    }
+
+    // same for ready groups, each match is the rooms of every group of one game
+    // and leaves the queue
+    pub fn match_games(&mut self, now: i64) -> Vec<Vec<Vec<u32>>> {
+        let mut res = vec![];
+        if self.groups.len() < self.match_size {
+            return res;
+        }
+        let anchors: Vec<(i32, u32)> = self.ready.iter().cloned().collect();
+        for key in anchors {
+            if !self.ready.contains(&key) {
+                continue;
+            }
+            let anchor = Rc::clone(&self.groups[&key.1]);
+            if anchor.borrow().game_status != 0 {
+                continue;
+            }
+            let (tier, queue_time) = (anchor.borrow().honor, anchor.borrow().queue_time);
+            let window = self.widen.window(queue_time, now);
+            let mut fg: ReadyGameData = Default::default();
+            let mut total_score = 0;
+            let mut members = vec![];
+            let mut next = Some(key);
+            while let Some((score, gid)) = next {
+                let rg = Rc::clone(&self.groups[&gid]);
+                let rg = rg.borrow();
+                if fg.team_len == 0 {
+                    fg.honor = rg.honor;
+                    fg.allow_same_hero = rg.allow_same_hero;
+                }
+                fg.group.push(rg.rid.clone());
+                fg.gid.push(gid);
+                fg.block.extend(rg.block.iter().cloned());
+                fg.user_name.extend(rg.user_name.iter().cloned());
+                fg.hero.extend(rg.hero.iter().cloned());
+                fg.team_len += 1;
+                total_score += score;
+                self.ready.remove(&(score, gid));
+                members.push((score, gid));
+                if fg.team_len == self.match_size {
+                    break;
+                }
+                let (groups, honor) = (&self.groups, &self.honor);
+                next = nearest(&self.ready, total_score / fg.team_len as i32, window, |gid| {
+                    let rg = groups[&gid].borrow();
+                    honor.fits(tier, queue_time, rg.honor, rg.queue_time, now) && group_fits(&fg, &rg)
+                });
+            }
+            if fg.team_len == self.match_size {
+                let teams = if self.balance { self.balance_teams(&fg.group) } else { None };
+                for gid in &fg.gid {
+                    if let Some(rg) = self.groups.remove(gid) {
+                        for rid in &rg.borrow().rid {
+                            self.rooms.remove(rid);
+                        }
+                    }
+                }
+                res.push(teams.unwrap_or(fg.group));
+            } else {
+                for m in members {
+                    self.ready.insert(m);
+                }
+            }
+        }
+        res
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Instant;
+
+    fn room(mode: &str, rid: u32, size: i16, score: i32) -> QueueRoomData {
+        let mut avg = BTreeMap::new();
+        avg.insert(mode.to_string(), score);
+        QueueRoomData {
+            rid: rid,
+            user_len: size,
+            user_name: (0..size).map(|i| format!("{}-{}", rid, i)).collect(),
+            avg: avg,
+            allow_same_hero: true,
+            queue_cnt: 1,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_queue_index() {
+        assert_eq!(WidenCfg {initial: 50, growth: 10.0, cap: 300}.window(100, 110), 150);
+        assert_eq!(WidenCfg {initial: 50, growth: 10.0, cap: 300}.window(100, 200), 300);
+        let mut q = QueueIndex::new("rk5p2t".to_string(), 2, 2, WidenCfg {initial: 50, growth: 10.0, cap: 300}, false, HonorCfg::default());
+        for (rid, size, score) in [(1, 1, 1000), (2, 1, 1020), (3, 2, 1010), (4, 1, 1400), (5, 1, 1425)].iter() {
+            q.insert_room(room("rk5p2t", *rid, *size, *score));
+        }
+        q.rooms[&2].borrow_mut().blacklist.push("1-0".to_string());
+        q.match_groups(0);
+        // the duo is a team alone, 1 and 2 refuse each other, 4 and 5 pair up far from the duo
+        assert_eq!(q.groups.len(), 2);
+        assert_eq!(q.free_len(), 2);
+        assert!(q.match_games(0).is_empty());
+
+        q.insert_room(room("rk5p2t", 6, 1, 990));
+        q.match_groups(0);
+        assert_eq!(q.rooms[&1].borrow().gid, q.rooms[&6].borrow().gid);
+        let games = q.match_games(0);
+        assert_eq!(games.len(), 1);
+        assert!(games[0].contains(&vec![3]));
+        assert!(q.rooms.get(&3).is_none());
+
+        // leaving breaks the group up and the partner waits alone
+        q.remove_room(4);
+        assert_eq!(q.groups.len(), 0);
+        assert_eq!(q.free_len(), 2);
+
+        // the window widens with the time waited
+        q.insert_room(room("rk5p2t", 7, 1, 1200));
+        q.match_groups(0);
+        assert_eq!(q.groups.len(), 0);
+        q.match_groups(15);
+        assert_eq!(q.rooms[&2].borrow().gid, q.rooms[&7].borrow().gid);
+        assert_eq!(q.free_len(), 1);
+    }
+
+    #[test]
+    fn test_honor_tiers() {
+        let honor = HonorCfg {tiers: vec![1000, 2000], delay: 30, merge_wait: 60};
+        assert_eq!((honor.tier(999), honor.tier(1000), honor.tier(2500)), (0, 1, 2));
+        let mut q = QueueIndex::new("rk5p2t".to_string(), 2, 2, WidenCfg {initial: 100, growth: 0.0, cap: 100}, false, honor);
+        for (rid, tier, score) in [(1, 0, 1000), (2, 0, 1010), (3, 1, 1020), (4, 2, 1030), (5, 0, 1040)].iter() {
+            let mut r = room("rk5p2t", *rid, 1, *score);
+            r.honor = *tier;
+            q.insert_room(r);
+        }
+        // low priority waits out the delay, other tiers keep apart until the merge wait
+        q.match_groups(0);
+        assert_eq!(q.groups.len(), 0);
+        q.match_groups(30);
+        assert_eq!(q.groups.len(), 1);
+        assert_eq!(q.rooms[&1].borrow().gid, q.rooms[&2].borrow().gid);
+        q.match_groups(60);
+        assert_eq!(q.rooms[&3].borrow().gid, q.rooms[&4].borrow().gid);
+        // the low priority room never merges up
+        assert_eq!(q.free_len(), 1);
+        assert_eq!(q.rooms[&5].borrow().gid, 0);
+        // the top tier merges down on its own wait even if the tier below just queued
+        let honor = HonorCfg {tiers: vec![1000, 2000], delay: 30, merge_wait: 60};
+        let mut q = QueueIndex::new("rk5p2t".to_string(), 2, 2, WidenCfg {initial: 100, growth: 0.0, cap: 100}, false, honor);
+        for (rid, tier, score, queue_time) in [(1, 2, 1000, 0), (2, 1, 1010, 50)].iter() {
+            let mut r = room("rk5p2t", *rid, 1, *score);
+            r.honor = *tier;
+            r.queue_time = *queue_time;
+            q.insert_room(r);
+        }
+        q.match_groups(59);
+        assert_eq!(q.groups.len(), 0);
+        q.match_groups(60);
+        assert_eq!(q.groups.len(), 1);
+        assert_eq!(q.rooms[&1].borrow().gid, q.rooms[&2].borrow().gid);
+    }
+
+    #[test]
+    fn test_balance_teams() {
+        let mut q = QueueIndex::new("rk5p2t".to_string(), 3, 2, WidenCfg {initial: 100, growth: 0.0, cap: 100}, true, HonorCfg::default());
+        for (rid, size, score) in [(1, 2, 1500), (2, 1, 1000), (3, 1, 1600), (4, 1, 1450), (5, 1, 1100)].iter() {
+            q.insert_room(room("rk5p2t", *rid, *size, *score));
+        }
+        // the duo takes the solo that leaves the gap smallest
+        let teams = q.balance_teams(&vec![vec![1, 2], vec![3, 4, 5]]).unwrap();
+        assert_eq!(teams, vec![vec![1, 5], vec![3, 4, 2]]);
+        q.rooms[&5].borrow_mut().blacklist.push("1-0".to_string());
+        let teams = q.balance_teams(&vec![vec![1, 2], vec![3, 4, 5]]).unwrap();
+        assert_eq!(teams, vec![vec![1, 2], vec![3, 4, 5]]);
+    }
+
+    // cargo test --release -- --ignored --nocapture bench_queue_tick
+    #[test]
+    #[ignore]
+    fn bench_queue_tick() {
+        let mut q = QueueIndex::new("rk5p2t".to_string(), 5, 2, WidenCfg {initial: 20, growth: 20.0, cap: 400}, true, HonorCfg {tiers: vec![1000], delay: 5, merge_wait: 10});
+        let mut seed: u64 = 7;
+        let mut rid = 0;
+        for tick in 0..10i64 {
+            // top the queue back up to 10k rooms before every tick
+            while q.rooms.len() < 10000 {
+                rid += 1;
+                seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+                let score = 600 + (seed >> 33) as i32 % 1800;
+                let size = [1, 1, 1, 2, 3, 5][(seed >> 20) as usize % 6];
+                let mut r = room("rk5p2t", rid, size, score);
+                r.honor = ((seed >> 40) % 4 != 0) as u8;
+                r.queue_time = tick;
+                q.insert_room(r);
+            }
+            let now = Instant::now();
+            q.match_groups(tick);
+            let games = q.match_games(tick);
+            println!("tick {}: {:?}, {} games, {} rooms and {} groups left", tick, now.elapsed(), games.len(), q.rooms.len(), q.groups.len());
+        }
+    }
+}
@@ -0,0 +1,173 @@
+use serde_derive::{Serialize, Deserialize};
+use std::collections::{HashMap, HashSet};
+
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct ReviewCfg {
+    // seconds of game history kept per pair and per player
+    pub window: i64,
+    // games against the same opponent within the window
+    pub pair_games: u32,
+    // share of those games won by one side, checked from win_games games on
+    pub win_rate: f32,
+    pub win_games: u32,
+    // games shorter than short_game seconds, flagged at short_games of them
+    pub short_game: i64,
+    pub short_games: u32,
+    // rating changes of flagged players stop until the flag is resolved
+    pub freeze: bool,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct ReviewFlag {
+    pub mode: String,
+    pub id: String,
+    // the opponent of a pair flag, empty for short games
+    pub other: String,
+    pub reason: String,
+    pub games: u32,
+    pub wins: u32,
+}
+
+pub const REVIEW_REPEAT: &str = "repeat_opponent";
+pub const REVIEW_WIN_TRADE: &str = "win_trade";
+pub const REVIEW_SHORT: &str = "short_games";
+
+// settled games between opposing accounts of one mode, to spot rating farming
+#[derive(Clone, Debug)]
+pub struct Review {
+    pub mode: String,
+    pub cfg: ReviewCfg,
+    // (a, b) with a < b, each game is its time and whether a won
+    pairs: HashMap<(String, String), Vec<(i64, bool)>>,
+    short: HashMap<String, Vec<i64>>,
+    // open flags, one per pair and reason
+    flagged: HashSet<(String, String, String)>,
+}
+
+fn pair_key(a: &String, b: &String) -> (String, String) {
+    if a < b { (a.clone(), b.clone()) } else { (b.clone(), a.clone()) }
+}
+
+impl Review {
+    pub fn new(mode: String, cfg: ReviewCfg) -> Review {
+        Review {mode: mode, cfg: cfg, pairs: HashMap::new(), short: HashMap::new(), flagged: HashSet::new()}
+    }
+
+    // a flag still waiting for review is not raised again
+    pub fn open(&mut self, id: &String, other: &String, reason: &str) {
+        let (a, b) = pair_key(id, other);
+        self.flagged.insert((a, b, reason.to_string()));
+    }
+
+    pub fn resolve(&mut self, id: &String, other: &String, reason: &str) {
+        let (a, b) = pair_key(id, other);
+        self.flagged.remove(&(a, b, reason.to_string()));
+    }
+
+    pub fn has_open(&self, id: &String) -> bool {
+        self.flagged.iter().any(|(a, b, _)| a == id || b == id)
+    }
+
+    fn raise(&mut self, res: &mut Vec<ReviewFlag>, id: &String, other: &String, reason: &str, games: u32, wins: u32) {
+        let (a, b) = pair_key(id, other);
+        if self.flagged.insert((a, b, reason.to_string())) {
+            res.push(ReviewFlag {mode: self.mode.clone(), id: id.clone(), other: other.clone(), reason: reason.to_string(), games: games, wins: wins});
+        }
+    }
+
+    // teams from first to last place, every team beat the teams after it
+    // duration is None when the game start is unknown
+    pub fn add_game(&mut self, placement: &Vec<Vec<String>>, duration: Option<i64>, now: i64) -> Vec<ReviewFlag> {
+        let mut res = vec![];
+        let since = now - self.cfg.window;
+        for (i, winners) in placement.iter().enumerate() {
+            for losers in &placement[i+1..] {
+                for w in winners {
+                    for l in losers {
+                        let key = pair_key(w, l);
+                        let games = {
+                            let games = self.pairs.entry(key.clone()).or_default();
+                            games.retain(|x| x.0 >= since);
+                            games.push((now, *w == key.0));
+                            games.clone()
+                        };
+                        let n = games.len() as u32;
+                        let a_wins = games.iter().filter(|x| x.1).count() as u32;
+                        if n >= self.cfg.pair_games {
+                            self.raise(&mut res, w, l, REVIEW_REPEAT, n, if *w == key.0 { a_wins } else { n - a_wins });
+                        }
+                        if n >= self.cfg.win_games {
+                            let (top, bottom, wins) = if a_wins*2 >= n { (&key.0, &key.1, a_wins) } else { (&key.1, &key.0, n - a_wins) };
+                            if wins as f32 >= self.cfg.win_rate * n as f32 {
+                                self.raise(&mut res, top, bottom, REVIEW_WIN_TRADE, n, wins);
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        if let Some(d) = duration {
+            if d < self.cfg.short_game {
+                for id in placement.iter().flatten() {
+                    let n = {
+                        let times = self.short.entry(id.clone()).or_default();
+                        times.retain(|x| *x >= since);
+                        times.push(now);
+                        times.len() as u32
+                    };
+                    if n >= self.cfg.short_games {
+                        self.raise(&mut res, id, &String::new(), REVIEW_SHORT, n, 0);
+                    }
+                }
+            }
+        }
+        res
+    }
+
+    // games from before a restart count toward later flags without raising their own
+    pub fn replay(&mut self, placement: &Vec<Vec<String>>, now: i64) {
+        for flag in self.add_game(placement, None, now) {
+            self.resolve(&flag.id, &flag.other, &flag.reason);
+        }
+    }
+
+    // drop history that left the window
+    pub fn prune(&mut self, now: i64) {
+        let since = now - self.cfg.window;
+        self.pairs.retain(|_, v| { v.retain(|x| x.0 >= since); v.len() > 0 });
+        self.short.retain(|_, v| { v.retain(|x| *x >= since); v.len() > 0 });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_review() {
+        let cfg = ReviewCfg {window: 3600, pair_games: 5, win_rate: 0.9, win_games: 3, short_game: 300, short_games: 2, freeze: true};
+        let mut review = Review::new("rk1p2t".to_string(), cfg);
+        let (a, b) = ("a".to_string(), "b".to_string());
+        let game = vec![vec![b.clone()], vec![a.clone()]];
+        assert!(review.add_game(&game, Some(600), 0).is_empty());
+        assert!(review.add_game(&game, Some(600), 10).is_empty());
+        let flags = review.add_game(&game, Some(100), 20);
+        assert_eq!(flags.len(), 1);
+        assert_eq!((flags[0].reason.as_str(), &flags[0].id, flags[0].wins), (REVIEW_WIN_TRADE, &b, 3));
+        let flags = review.add_game(&game, Some(100), 30);
+        assert_eq!(flags.iter().filter(|x| x.reason == REVIEW_SHORT).count(), 2);
+        let flags = review.add_game(&game, Some(600), 40);
+        assert_eq!(flags.iter().map(|x| x.reason.as_str()).collect::<Vec<&str>>(), vec![REVIEW_REPEAT]);
+
+        // games that left the window no longer count
+        review.resolve(&a, &b, REVIEW_REPEAT);
+        assert!(review.has_open(&a));
+        review.resolve(&a, &b, REVIEW_WIN_TRADE);
+        review.resolve(&a, &String::new(), REVIEW_SHORT);
+        review.resolve(&b, &String::new(), REVIEW_SHORT);
+        assert!(!review.has_open(&a) && !review.has_open(&b));
+        assert!(review.add_game(&game, Some(600), 3650).is_empty());
+        review.prune(7300);
+        assert!(review.pairs.is_empty() && review.short.is_empty());
+    }
+}
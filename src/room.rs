@@ -2,13 +2,14 @@ use serde::{Serializer, Deserializer};
 use serde_derive::{Serialize, Deserialize};
 use std::cell::RefCell;
 use std::rc::Rc;
-use std::collections::{HashMap, BTreeMap};
+use std::collections::{HashMap, BTreeMap, BTreeSet};
 use std::time::{Duration, Instant, SystemTime};
 use crate::msg::*;
 use crossbeam_channel::{bounded, tick, Sender, Receiver, select};
 use failure::Error;
 use rust_decimal::Decimal;
-use crate::event_room::UserGift;
+use crate::event_room::{UserGift, ScoreRes, UserInfoData};
+use crate::elo::{EloRank, GLICKO2_DEFAULT_RD, GLICKO2_DEFAULT_VOL, TRUESKILL_DEFAULT_SIGMA};
 
 #[derive(Clone, Debug, PartialEq, Default)]
 pub struct GameServer {
@@ -123,6 +124,9 @@ pub struct User {
     pub hero: String,
     pub honor: i32,
     pub ban: BanTime,
+    // leaves counted within the reset window
+    pub leaves: u32,
+    pub last_leave: i64,
     pub info: PlayerInfo,
     pub rank: BTreeMap<String, ScoreInfo>,
     pub rid: u32,
@@ -144,14 +148,75 @@ pub struct PlayerInfo {
     pub Money: u32,
     pub TotalCurrency: u32,
     pub TotalEquip: Vec<UserEquInfo>,
+    // best mmr over the user's modes
     pub HiddenRank: u32,
 }
 
-#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+// rows saved before a field existed read it from Default
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+#[serde(default)]
 pub struct ScoreInfo {
-    pub score: i16,
+    pub score: i32,
+    // hidden rating used for matching and expected score, never sent to clients
+    #[serde(skip_serializing)]
+    pub mmr: i32,
     pub WinCount: u32,
     pub LoseCount: u32,
+    pub rd: f32,
+    pub vol: f32,
+    // trueskill deviation, kept apart from the glicko2 rd
+    pub sigma: f32,
+    // placement games left, the player is unranked until it reaches 0
+    pub placement: u32,
+    // best score and record of the running season
+    pub peak: i32,
+    pub season_win: u32,
+    pub season_lose: u32,
+    // unix time of the last game and of the last decay step
+    pub last_game: i64,
+    pub last_decay: i64,
+    // points lost to decay the player has not been told about yet
+    pub decayed: i32,
+    // wins in a row when positive, losses in a row when negative
+    pub streak: i32,
+    // losses left that cannot drop the player out of the tier
+    pub shield: u32,
+    // rating changes held while a review flag of the mode is open
+    #[serde(skip)]
+    pub frozen: bool,
+    // offset on the mmr per hero played in the mode
+    pub heroes: BTreeMap<String, HeroRating>,
+}
+
+#[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
+pub struct HeroRating {
+    pub mmr: i32,
+    pub games: u32,
+}
+
+impl Default for ScoreInfo {
+    fn default() -> ScoreInfo {
+        ScoreInfo {
+            score: 1000,
+            mmr: 1000,
+            WinCount: 0,
+            LoseCount: 0,
+            rd: GLICKO2_DEFAULT_RD,
+            vol: GLICKO2_DEFAULT_VOL,
+            sigma: TRUESKILL_DEFAULT_SIGMA,
+            placement: 0,
+            peak: 1000,
+            season_win: 0,
+            season_lose: 0,
+            last_game: 0,
+            last_decay: 0,
+            decayed: 0,
+            streak: 0,
+            shield: 0,
+            frozen: false,
+            heroes: BTreeMap::new(),
+        }
+    }
 }
 
 #[derive(Clone, Debug, PartialEq, Default, Serialize, Deserialize)]
@@ -168,13 +233,25 @@ pub struct RoomData {
     pub master: String,
     pub last_master: String,
     pub mode: String,
-    pub avg: BTreeMap<String, i16>,
+    pub avg: BTreeMap<String, i32>,
     pub avg_honor: i32,
     pub ready: i8,
     pub queue_cnt: i16,
+    // unix time of start queue, kept when the room is queued again after a failed prestart
+    pub queue_time: i64,
+    // mode queues the room waits in, narrowed to the matched mode once a match takes it
+    pub queue_modes: Vec<String>,
+    // modes asked for at start queue, a cancelled prestart queues the room in all of them again
+    pub start_modes: Vec<String>,
     pub modes: Rc<RefCell<Vec<String>>>,
 }
 
+impl User {
+    pub fn update_hidden_rank(&mut self) {
+        self.info.HiddenRank = self.rank.values().map(|s| s.mmr.max(0) as u32).max().unwrap_or(0);
+    }
+}
+
 impl RoomData {
     pub fn update_avg(&mut self) {
         let mut sum_honor = 0;
@@ -186,7 +263,7 @@ impl RoomData {
             for (m, score) in &user.borrow().rank {
                 //self.avg[&*m] += score.score;
                 if let Some(avg) = self.avg.get_mut(&*m) {
-                    *avg += score.score;
+                    *avg += score.mmr;
                 }
             }
             sum_honor += user.borrow().honor;
@@ -194,9 +271,9 @@ impl RoomData {
         if self.users.len() > 0 {
             self.avg_honor = sum_honor/self.users.len() as i32;
             for m in self.modes.borrow().iter() {
-                //self.avg[&*m] = (self.avg[&*m] as usize / self.users.len()) as i16;
+                //self.avg[&*m] = (self.avg[&*m] as usize / self.users.len()) as i32;
                 if let Some(avg) = self.avg.get_mut(&*m) {
-                    *avg = (*avg as usize / self.users.len()) as i16;
+                    *avg = *avg / self.users.len() as i32;
                 }
             }
         }
@@ -363,6 +440,18 @@ impl FightGroup {
         self.user_order.sort_by_key(|x| x.borrow().rank.get(&mode).unwrap().score);
     }
 
+    // mmr average of the group, rooms weighted by their size
+    pub fn get_avg(&self, mode: &String) -> f32 {
+        let mut sum = 0.0;
+        let mut count = 0;
+        for room in &self.rooms {
+            let r = room.borrow();
+            sum += r.avg.get(mode).cloned().unwrap_or(0) as f32 * r.users.len() as f32;
+            count += r.users.len();
+        }
+        if count > 0 { sum/count as f32 } else { 0.0 }
+    }
+
     pub fn get_group_order(&mut self, mode: String) -> Vec<String> {
         self.update_group_order(mode);
         let mut res: Vec<String> = vec![];
@@ -490,11 +579,32 @@ pub struct FightGame {
     pub user_count: u16,
     pub winteam: Vec<String>,
     pub loseteam: Vec<String>,
+    // teams from first to last place
+    pub placement: Vec<Vec<String>>,
+    // players flagged afk or abandoned in game info
+    pub afk: Vec<String>,
     pub game_status: u16,
     pub game_port: u16,
     pub server_name: String,
     pub game_start: bool,
+    // unix time the game server started the game, 0 before that
+    pub start_time: i64,
     pub server_notify: i8,
+    // settlement result of every player, filled at game over
+    pub score_res: BTreeMap<String, ScoreRes>,
+    // partial play weights from game over, kept while settlement waits for game info
+    pub weight: BTreeMap<String, f32>,
+    pub settled: bool,
+    // game info players that came before game over, handled again once game over arrives
+    pub info: Option<Vec<UserInfoData>>,
+    // unix time game info was held and game over arrived, 0 before that
+    pub info_time: i64,
+    pub over_time: i64,
+    // hero offsets were moved by this game
+    pub hero_rated: bool,
+    // mmr average and predicted win probability of every team at prestart, in teams order
+    pub avg: Vec<f32>,
+    pub expected: Vec<f32>,
 }
 
 #[derive(PartialEq)]
@@ -525,6 +635,48 @@ impl FightGame {
         }
     }
 
+    pub fn update_expected(&mut self) {
+        let elo = EloRank {k: 0.0};
+        self.avg = self.teams.iter().map(|t| t.borrow().get_avg(&self.mode)).collect();
+        self.expected = if self.avg.len() > 1 {
+            (0..self.avg.len()).map(|i| elo.get_expected_placement(self.avg[i], &self.avg, i)).collect()
+        } else {
+            vec![]
+        };
+    }
+
+    // a placement ordering has every team of the game exactly once
+    pub fn check_rank(&self, rank: &Vec<Vec<String>>) -> bool {
+        let mut teams: Vec<BTreeSet<String>> = vec![];
+        for t in &self.teams {
+            let mut ids = BTreeSet::new();
+            for r in &t.borrow().rooms {
+                for u in &r.borrow().users {
+                    ids.insert(u.borrow().id.clone());
+                }
+            }
+            teams.push(ids);
+        }
+        if rank.len() != teams.len() {
+            return false;
+        }
+        for entry in rank {
+            let set: BTreeSet<String> = entry.iter().cloned().collect();
+            match teams.iter().position(|t| *t == set) {
+                Some(i) if set.len() == entry.len() => { teams.remove(i); }
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    // index in teams of the player's team
+    pub fn team_of(&self, id: &String) -> Option<usize> {
+        self.teams.iter().position(|t| {
+            t.borrow().rooms.iter().any(|r| r.borrow().users.iter().any(|u| u.borrow().id == *id))
+        })
+    }
+
     pub fn check_prestart_get(&self) -> bool {
         let mut res = false;
         for c in &self.teams {